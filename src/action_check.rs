@@ -0,0 +1,300 @@
+use anyhow::bail;
+use clpack::AppContext;
+use clpack::entry::Entry;
+use clpack::git::run_git;
+use clpack::store::Store;
+use colored::Colorize;
+use std::path::Path;
+
+/// CI variables with the base commit or target branch of a merge request, in order of preference.
+/// Branch names are prefixed with `origin/`.
+const BASE_ENV_VARS: [(&str, bool); 3] = [
+    // GitLab
+    ("CI_MERGE_REQUEST_DIFF_BASE_SHA", false),
+    ("CI_MERGE_REQUEST_TARGET_BRANCH_NAME", true),
+    // GitHub Actions
+    ("GITHUB_BASE_REF", true),
+];
+
+/// Args for cl_check()
+#[derive(Debug, Default)]
+pub struct ClCheck {
+    /// Git ref to compare against
+    pub base: Option<String>,
+    /// Merge request labels
+    pub labels: Vec<String>,
+}
+
+/// Find the ref to compare with - from the command line or CI environment
+fn resolve_base(base: Option<String>) -> anyhow::Result<String> {
+    if let Some(base) = base {
+        return Ok(base);
+    }
+
+    for (var, is_branch) in BASE_ENV_VARS {
+        if let Ok(value) = std::env::var(var)
+            && !value.is_empty()
+        {
+            return Ok(if is_branch {
+                format!("origin/{value}")
+            } else {
+                value
+            });
+        }
+    }
+
+    bail!("Base ref to compare with is not known, use --base (e.g. --base origin/main).");
+}
+
+/// Labels of the pull request in GitHub Actions - there is no variable with them, they are read from the event payload
+fn github_labels() -> Vec<String> {
+    let Ok(path) = std::env::var("GITHUB_EVENT_PATH") else {
+        return vec![];
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(payload) => parse_github_event_labels(&payload),
+        Err(e) => {
+            log::warn!("Failed to read GitHub event payload {path}: {e}");
+            vec![]
+        }
+    }
+}
+
+/// Get label names of the pull request from a GitHub event payload
+fn parse_github_event_labels(payload: &str) -> Vec<String> {
+    let Ok(event) = serde_json::from_str::<serde_json::Value>(payload) else {
+        return vec![];
+    };
+    event["pull_request"]["labels"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|label| label["name"].as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Perform the action of checking that the current branch adds a changelog entry
+pub(crate) fn cl_check(ctx: AppContext, opts: ClCheck) -> anyhow::Result<()> {
+    let check_config = &ctx.config.check;
+    let base = resolve_base(opts.base)?;
+
+    println!("Comparing with: {}", base.green().bold());
+
+    let labels = if opts.labels.is_empty() {
+        github_labels()
+    } else {
+        opts.labels
+    };
+
+    let skip_label = check_config.skip_label.trim();
+    if !skip_label.is_empty() && labels.iter().any(|l| l.trim() == skip_label) {
+        println!(
+            "{}",
+            format!("Check skipped by label {skip_label}.").green()
+        );
+        return Ok(());
+    }
+
+    let skip_trailer = check_config.skip_trailer.trim();
+    if !skip_trailer.is_empty() {
+        let messages = run_git(
+            &ctx,
+            &["log", "--format=%B", &format!("{base}..HEAD"), "--"],
+        )?;
+        if messages
+            .lines()
+            .any(|line| line.trim().eq_ignore_ascii_case(skip_trailer))
+        {
+            println!(
+                "{}",
+                format!("Check skipped by commit trailer \"{skip_trailer}\".").green()
+            );
+            return Ok(());
+        }
+    }
+
+    let entries_dir = Path::new(&ctx.config.data_folder).join("entries");
+    let entries_dir = entries_dir.to_string_lossy();
+
+    let added = run_git(
+        &ctx,
+        &[
+            "diff",
+            "--name-only",
+            "--diff-filter=A",
+            "--relative",
+            &format!("{base}...HEAD"),
+            "--",
+            &entries_dir,
+        ],
+    )?;
+
+    // Only files picked up as entries when packing count - not e.g. ones in other subfolders
+    let entry_names = Store::new(&ctx, false)?.entry_names()?;
+    let added: Vec<&str> = added
+        .lines()
+        .map(str::trim)
+        .filter(|file| {
+            let name = file
+                .strip_prefix(entries_dir.as_ref())
+                .and_then(|f| f.strip_prefix('/'))
+                .and_then(|f| f.strip_suffix(".md"));
+            let is_entry = name.is_some_and(|name| entry_names.iter().any(|e| e == name));
+            if !is_entry && !file.is_empty() {
+                eprintln!(
+                    "{}",
+                    format!("{file}: not picked up as a changelog entry, ignored").yellow()
+                );
+            }
+            is_entry
+        })
+        .collect();
+
+    if added.is_empty() {
+        let mut skip_hints = vec![];
+        if !skip_label.is_empty() {
+            skip_hints.push(format!("label \"{skip_label}\""));
+        }
+        if !skip_trailer.is_empty() {
+            skip_hints.push(format!("commit trailer \"{skip_trailer}\""));
+        }
+        let skip_hint = if skip_hints.is_empty() {
+            String::new()
+        } else {
+            format!(
+                " For trivial changes, skip the check with {}.",
+                skip_hints.join(" or ")
+            )
+        };
+        bail!(
+            "No changelog entry added on this branch (compared to {base}). Add one with `{} add`.{skip_hint}",
+            ctx.binary_name
+        );
+    }
+
+    let mut problems = vec![];
+    for file in &added {
+        let entry = match Entry::load(ctx.root.join(file)) {
+            Ok(entry) => entry,
+            Err(e) => {
+                problems.push(format!("{file}: {e}"));
+                continue;
+            }
+        };
+
+        if entry.is_empty() {
+            problems.push(format!("{file}: entry has no content"));
+            continue;
+        }
+
+        for section in entry.sections.keys() {
+            if section.is_empty() {
+                eprintln!(
+                    "{}",
+                    format!("{file}: some lines are not under any section").yellow()
+                );
+            } else if !ctx
+                .config
+                .channels
+                .keys()
+                .any(|ch| ctx.config.for_channel(ch).sections.contains(section))
+            {
+                eprintln!(
+                    "{}",
+                    format!("{file}: section \"{section}\" is not among the configured sections")
+                        .yellow()
+                );
+            }
+        }
+
+        for package in &entry.meta.packages {
+            if !ctx.config.packages.contains_key(package) {
+                problems.push(format!(
+                    "{file}: unknown package \"{package}\" in front-matter"
+                ));
+            }
+        }
+
+        for channel in &entry.meta.channels {
+            if !ctx.config.channels.contains_key(channel)
+                && !ctx
+                    .config
+                    .packages
+                    .values()
+                    .any(|package| package.channels.contains_key(channel))
+            {
+                problems.push(format!(
+                    "{file}: unknown channel \"{channel}\" in front-matter"
+                ));
+            }
+        }
+
+        println!("Changelog entry: {}", file.cyan());
+    }
+
+    if !problems.is_empty() {
+        bail!("Invalid changelog entries:\n{}", problems.join("\n"));
+    }
+
+    println!("{}", "Changelog check passed.".green());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{git, init_repo};
+    use clpack::Config;
+
+    #[test]
+    fn test_parse_github_event_labels() {
+        let payload =
+            r#"{"pull_request": {"labels": [{"name": "bug"}, {"name": "no-changelog"}]}}"#;
+        assert_eq!(
+            parse_github_event_labels(payload),
+            vec!["bug", "no-changelog"]
+        );
+        assert!(parse_github_event_labels(r#"{"ref": "refs/heads/main"}"#).is_empty());
+        assert!(parse_github_event_labels("not json").is_empty());
+    }
+
+    #[test]
+    fn test_check() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        init_repo(dir);
+        std::fs::create_dir_all(dir.join("changelog/entries/drafts")).unwrap();
+        git(dir, &["checkout", "-q", "-b", "feature"]);
+
+        let check = |labels: &[&str]| {
+            cl_check(
+                AppContext::new(dir, Config::default()),
+                ClCheck {
+                    base: Some("main".to_string()),
+                    labels: labels.iter().map(|l| l.to_string()).collect(),
+                },
+            )
+        };
+
+        // Missing
+        let err = check(&[]).unwrap_err();
+        assert!(
+            err.to_string().contains("No changelog entry added"),
+            "{err}"
+        );
+        check(&["bug", "no-changelog"]).unwrap();
+
+        // Not in a folder picked up when packing
+        std::fs::write(dir.join("changelog/entries/drafts/login.md"), "- Login\n").unwrap();
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "-q", "-m", "Draft"]);
+        assert!(check(&[]).is_err());
+
+        // Added
+        std::fs::write(dir.join("changelog/entries/login.md"), "# Fixes\n- Login\n").unwrap();
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "-q", "-m", "Entry"]);
+        check(&[]).unwrap();
+    }
+}
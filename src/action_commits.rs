@@ -0,0 +1,372 @@
+use crate::action_log::notify_entry_created;
+use crate::action_pack::resolve_channel;
+use crate::ui;
+use anyhow::bail;
+use clpack::AppContext;
+use clpack::config::{ChannelName, ConventionalCommitsConfig, EntryName};
+use clpack::entry::EntryMeta;
+use clpack::git::{get_branch_name, run_git};
+use clpack::store::Store;
+use colored::Colorize;
+use indexmap::IndexMap;
+
+use std::path::Path;
+use std::sync::LazyLock;
+
+/// `type(scope)!: description`
+static CONVENTIONAL_SUBJECT: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(r"^(?P<type>[A-Za-z]+)(?:\((?P<scope>[^)]*)\))?(?P<bang>!)?: *(?P<desc>.+)$")
+        .unwrap()
+});
+
+/// `#123` (GitLab / GitHub) or `SW-123` (YouTrack / Jira)
+static ISSUE_REFERENCE: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"(?:#\d+\b|\b[A-Z][A-Z0-9]+-\d+\b)").unwrap());
+
+/// `Merge branch 'foo' into 'main'`
+static MERGED_BRANCH: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"^Merge (?:remote-tracking )?branch '([^']+)'").unwrap());
+
+/// Args for cl_from_commits()
+#[derive(Debug, Default)]
+pub struct ClFromCommits {
+    /// Channel whose last release marks the start of the history to scan
+    pub channel: Option<ChannelName>,
+    /// Explicit git ref to start from, instead of the last release
+    pub since: Option<String>,
+    /// One entry per merge commit, instead of per commit
+    pub merges: bool,
+    /// Only show what would be written
+    pub dry_run: bool,
+}
+
+/// A git commit, as read from the log
+#[derive(Debug, Clone, PartialEq)]
+struct Commit {
+    sha: String,
+    author: String,
+    message: String,
+}
+
+/// A commit message parsed as a Conventional Commit
+#[derive(Debug, PartialEq)]
+struct ConventionalCommit {
+    commit_type: String,
+    scope: Option<String>,
+    description: String,
+    breaking: bool,
+    issues: Vec<String>,
+}
+
+/// Perform the action of generating changelog entries from git history
+pub(crate) fn cl_from_commits(ctx: AppContext, opts: ClFromCommits) -> anyhow::Result<()> {
+    let store = Store::new(&ctx, false)?;
+    let cc_config = &ctx.config.conventional_commits;
+
+    let since = match opts.since {
+        Some(since) => Some(since),
+        None => {
+            let branch = get_branch_name(&ctx);
+            let channel =
+                resolve_channel(&ctx, opts.channel, branch.as_ref(), ui::is_interactive())?;
+            match store.last_release(&channel) {
+                None => None,
+                Some(release) => {
+                    let Some(commit) = &release.commit else {
+                        bail!(
+                            "The last release {} has no commit recorded (packed with an older clpack), use --since.",
+                            release.version
+                        );
+                    };
+                    println!("Last release: {} ({commit})", release.version.green());
+                    Some(commit.clone())
+                }
+            }
+        }
+    };
+
+    let range = match &since {
+        Some(since) => format!("{since}..HEAD"),
+        None => "HEAD".to_string(),
+    };
+
+    let entries_dir = Path::new(&ctx.config.data_folder).join("entries");
+    let entries_dir = entries_dir.to_string_lossy();
+
+    // Commits that already add an entry were logged by hand
+    let has_entry = |sha: &str| -> anyhow::Result<bool> {
+        let added = run_git(
+            &ctx,
+            &[
+                "diff-tree",
+                "--no-commit-id",
+                "--name-only",
+                "--diff-filter=A",
+                "-r",
+                sha,
+                "--",
+                &entries_dir,
+            ],
+        )?;
+        Ok(!added.trim().is_empty())
+    };
+
+    // Groups of commits, each group becomes one entry
+    let mut groups = Vec::<(EntryName, Vec<Commit>)>::new();
+    if opts.merges {
+        for merge in git_log(&ctx, &["--merges", "--first-parent", &range])? {
+            let merged = git_log(&ctx, &["--no-merges", &format!("{0}^1..{0}^2", merge.sha)])?;
+            let mut commits = vec![];
+            for commit in merged {
+                if !has_entry(&commit.sha)? {
+                    commits.push(commit);
+                }
+            }
+            let subject = merge.message.lines().next().unwrap_or_default();
+            let title = MERGED_BRANCH
+                .captures(subject)
+                .and_then(|c| c.get(1))
+                .map(|m| m.as_str())
+                .unwrap_or(subject);
+            groups.push((make_entry_name("merge", &merge.sha, title), commits));
+        }
+    } else {
+        for commit in git_log(&ctx, &["--no-merges", &range])? {
+            if has_entry(&commit.sha)? {
+                continue;
+            }
+            let subject = commit.message.lines().next().unwrap_or_default();
+            let title = CONVENTIONAL_SUBJECT
+                .captures(subject)
+                .and_then(|c| c.name("desc"))
+                .map(|m| m.as_str())
+                .unwrap_or(subject);
+            groups.push((make_entry_name("commit", &commit.sha, title), vec![commit]));
+        }
+    }
+
+    let mut written = 0;
+    for (name, commits) in groups {
+        let parsed: Vec<(&Commit, ConventionalCommit)> = commits
+            .iter()
+            .filter_map(|c| parse_conventional_commit(&c.message).map(|cc| (c, cc)))
+            .collect();
+
+        let Some(text) = render_entry(cc_config, &parsed) else {
+            continue;
+        };
+
+        if store.entry_exists(&name) {
+            eprintln!("Entry {name} already exists, skipped.");
+            continue;
+        }
+
+        if opts.dry_run {
+            println!("{}\n{text}", format!("{name}.md:").cyan());
+        } else {
+            store.create_entry(name.clone(), text.clone())?;
+            notify_entry_created(&ctx, &name, &text)?;
+        }
+        written += 1;
+    }
+
+    if written == 0 {
+        eprintln!("No new entries to write from Conventional Commits in {range}.");
+    } else if !opts.dry_run {
+        println!(
+            "{}",
+            format!("{written} entries written. Review and edit them before packing a release.")
+                .green()
+        );
+    }
+
+    Ok(())
+}
+
+/// Read commits from git log, newest first
+fn git_log(ctx: &AppContext, args: &[&str]) -> anyhow::Result<Vec<Commit>> {
+    let mut full_args = vec!["log", "--format=%H%x1f%an%x1f%B%x1e"];
+    full_args.extend_from_slice(args);
+    full_args.push("--");
+
+    let output = run_git(ctx, &full_args)?;
+    Ok(output
+        .split('\x1e')
+        .filter_map(|record| {
+            let mut fields = record.trim_start_matches('\n').splitn(3, '\x1f');
+            Some(Commit {
+                sha: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                message: fields.next()?.trim().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Build an entry name from the short commit hash and a title
+fn make_entry_name(prefix: &str, sha: &str, title: &str) -> EntryName {
+    let mut slug = String::new();
+    for c in title.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.ends_with('-') {
+            slug.push('-');
+        }
+        if slug.len() >= 40 {
+            break;
+        }
+    }
+    let short_sha = &sha[..sha.len().min(8)];
+    format!("{prefix}-{short_sha}-{}", slug.trim_matches('-'))
+}
+
+/// Parse a commit message. Returns None if it is not a Conventional Commit.
+fn parse_conventional_commit(message: &str) -> Option<ConventionalCommit> {
+    let subject = message.lines().next()?.trim();
+    let caps = CONVENTIONAL_SUBJECT.captures(subject)?;
+
+    let breaking = caps.name("bang").is_some()
+        || message.lines().any(|line| {
+            line.starts_with("BREAKING CHANGE:") || line.starts_with("BREAKING-CHANGE:")
+        });
+
+    let mut issues = Vec::<String>::new();
+    // Numeric references keep the `#`, so issue tracker integrations can tell them apart
+    for issue in ISSUE_REFERENCE.find_iter(message) {
+        let issue = issue.as_str().to_string();
+        if !issues.contains(&issue) {
+            issues.push(issue);
+        }
+    }
+
+    Some(ConventionalCommit {
+        commit_type: caps["type"].to_lowercase(),
+        scope: caps
+            .name("scope")
+            .map(|s| s.as_str().to_string())
+            .filter(|s| !s.is_empty()),
+        description: caps["desc"].trim().to_string(),
+        breaking,
+        issues,
+    })
+}
+
+/// Render entry text for a group of commits. None if none of them should be logged.
+fn render_entry(
+    config: &ConventionalCommitsConfig,
+    commits: &[(&Commit, ConventionalCommit)],
+) -> Option<String> {
+    let mut meta = EntryMeta::default();
+    let mut sections = IndexMap::<&str, Vec<String>>::new();
+
+    for (commit, cc) in commits {
+        let section = if cc.breaking {
+            config.breaking_section.as_str()
+        } else if let Some(section) = config.types.get(&cc.commit_type) {
+            section.as_str()
+        } else {
+            continue;
+        };
+
+        let line = match &cc.scope {
+            Some(scope) => format!("- {scope}: {}", cc.description),
+            None => format!("- {}", cc.description),
+        };
+        sections.entry(section).or_default().push(line);
+
+        meta.breaking |= cc.breaking;
+
+        for issue in &cc.issues {
+            if !meta.issues.contains(issue) {
+                meta.issues.push(issue.clone());
+            }
+        }
+        if !meta.authors.contains(&commit.author) {
+            meta.authors.push(commit.author.clone());
+        }
+    }
+
+    if sections.is_empty() {
+        return None;
+    }
+
+    let mut text = format!(
+        "+++\n{}+++\n",
+        toml::to_string(&meta).expect("entry metadata is serializable")
+    );
+    for (section, lines) in sections {
+        text.push_str(&format!("# {section}\n"));
+        for line in lines {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    Some(text)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_conventional_commit() {
+        assert_eq!(
+            parse_conventional_commit("fix(ui): crash on start (#12)\n\nRefs: SW-4711"),
+            Some(ConventionalCommit {
+                commit_type: "fix".to_string(),
+                scope: Some("ui".to_string()),
+                description: "crash on start (#12)".to_string(),
+                breaking: false,
+                issues: vec!["#12".to_string(), "SW-4711".to_string()],
+            })
+        );
+
+        assert!(
+            parse_conventional_commit("feat!: drop v1 API")
+                .unwrap()
+                .breaking
+        );
+        assert!(
+            parse_conventional_commit("feat: new API\n\nBREAKING CHANGE: v1 is gone")
+                .unwrap()
+                .breaking
+        );
+        assert_eq!(parse_conventional_commit("Update readme"), None);
+    }
+
+    #[test]
+    fn test_render_entry() {
+        let config = ConventionalCommitsConfig::default();
+        let commit = Commit {
+            sha: "abc".to_string(),
+            author: "Jane".to_string(),
+            message: String::new(),
+        };
+        let parsed = vec![
+            (&commit, parse_conventional_commit("fix: one (#1)").unwrap()),
+            (
+                &commit,
+                parse_conventional_commit("chore: bump deps").unwrap(),
+            ),
+            (
+                &commit,
+                parse_conventional_commit("feat(api)!: two").unwrap(),
+            ),
+        ];
+
+        assert_eq!(
+            render_entry(&config, &parsed).unwrap(),
+            "+++\nissues = [\"#1\"]\nauthors = [\"Jane\"]\nbreaking = true\n+++\n# Fixes\n- one (#1)\n# Breaking changes\n- api: two\n"
+        );
+
+        assert_eq!(render_entry(&config, &parsed[1..2]), None);
+    }
+
+    #[test]
+    fn test_make_entry_name() {
+        assert_eq!(
+            make_entry_name("commit", "0123456789abcdef", "Crash on start (#12)"),
+            "commit-01234567-crash-on-start-12"
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use anyhow::bail;
+use clpack::config::{Config, ConfigSource, LoadedConfig};
+use clpack::validate::ValidationReport;
+use colored::Colorize;
+
+/// Keys whose values are not printed
+const SECRET_KEYS: &[&str] = &["token"];
+
+/// Perform the action of printing the merged config with the source of each value
+pub(crate) fn cl_config_show(loaded: &LoadedConfig) -> anyhow::Result<()> {
+    if loaded.files.is_empty() {
+        println!("{}", "# No config files found, using defaults".dimmed());
+    }
+    for file in &loaded.files {
+        println!("{}", format!("# Loaded: {}", file.display()).dimmed());
+    }
+    println!();
+
+    let toml::Value::Table(table) = toml::Value::try_from(&loaded.config)? else {
+        unreachable!("config is a table");
+    };
+    print_table(loaded, &table, "");
+    Ok(())
+}
+
+/// Perform the action of printing the JSON Schema of the config file
+pub(crate) fn cl_config_schema() -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string_pretty(&Config::json_schema())?);
+    Ok(())
+}
+
+/// Perform the action of checking the merged config and reporting all problems
+pub(crate) fn cl_config_validate(loaded: &LoadedConfig) -> anyhow::Result<()> {
+    for file in &loaded.files {
+        println!("{}", format!("Checked: {}", file.display()).dimmed());
+    }
+
+    let report = loaded.config.validate();
+    print_report(&report);
+
+    if !report.errors.is_empty() {
+        bail!("The config has {} error(s).", report.errors.len());
+    }
+    if report.warnings.is_empty() {
+        println!("{}", "The config is valid.".green());
+    } else {
+        println!(
+            "{}",
+            format!(
+                "The config is valid, with {} warning(s).",
+                report.warnings.len()
+            )
+            .yellow()
+        );
+    }
+    Ok(())
+}
+
+/// Print all problems found in the config
+pub(crate) fn print_report(report: &ValidationReport) {
+    for error in &report.errors {
+        eprintln!("{}", format!("Error: {error}").red());
+    }
+    for warning in &report.warnings {
+        eprintln!("{}", format!("Warning: {warning}").yellow());
+    }
+}
+
+/// Print a table like in a TOML file - values first, then sub-tables with headers
+fn print_table(loaded: &LoadedConfig, table: &toml::Table, prefix: &str) {
+    let path_of = |key: &str| {
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{prefix}.{key}")
+        }
+    };
+
+    for (key, value) in table {
+        if value.is_table() {
+            continue;
+        }
+        let path = path_of(key);
+        let value = if SECRET_KEYS.contains(&key.as_str()) {
+            "\"(hidden)\"".to_string()
+        } else {
+            value.to_string()
+        };
+        let source = match loaded.source_of(&path) {
+            ConfigSource::Default => "# default".dimmed(),
+            source => format!("# {source}").cyan(),
+        };
+        println!("{key} = {value}  {source}");
+    }
+
+    for (key, value) in table {
+        if let toml::Value::Table(sub) = value {
+            let path = path_of(key);
+            // Tables with only sub-tables don't need a header
+            if sub.values().any(|v| !v.is_table()) {
+                println!("\n[{path}]");
+            }
+            print_table(loaded, sub, &path);
+        }
+    }
+}
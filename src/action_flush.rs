@@ -0,0 +1,48 @@
+use crate::ui;
+use clpack::AppContext;
+use clpack::store::Store;
+use colored::Colorize;
+
+/// Perform the action of removing entries already released on all channels
+pub(crate) fn cl_flush(ctx: AppContext, archive: bool) -> anyhow::Result<()> {
+    let mut store = Store::new(&ctx, false)?;
+
+    let plan = store.plan_flush();
+
+    if plan.entries.is_empty() && plan.releases.is_empty() {
+        eprintln!("Nothing to flush - no entries were released on all channels.");
+        return Ok(());
+    }
+
+    println!();
+    println!("Entries released on all channels:");
+    for entry in &plan.entries {
+        println!("- {}", entry.cyan());
+    }
+    println!();
+    for (channel, versions) in &plan.releases {
+        println!(
+            "Channel {}: releases {} will be marked as flushed (epoch {})",
+            channel.green().bold(),
+            versions.join(", "),
+            plan.epoch
+        );
+    }
+    println!();
+
+    let question = if archive {
+        "Continue - move the entries to the archive?"
+    } else {
+        "Continue - delete the entry files?"
+    };
+
+    if !ui::confirm(question, true)? {
+        eprintln!("{}", "Cancelled.".red());
+        return Ok(());
+    }
+
+    store.flush(&plan, archive)?;
+
+    println!("{}", "Changelog entries flushed.".green());
+    Ok(())
+}
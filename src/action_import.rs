@@ -0,0 +1,412 @@
+use anyhow::{Context, bail};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone};
+use clpack::AppContext;
+use clpack::config::{ChannelName, Config, EntryName, VersionName};
+use clpack::store::{Release, Store};
+use colored::Colorize;
+use std::path::PathBuf;
+
+/// Args for cl_import()
+#[derive(Debug)]
+pub struct ClImport {
+    /// Changelog file to import
+    pub file: PathBuf,
+    /// Channel to import into (default channel if None)
+    pub channel: Option<ChannelName>,
+}
+
+/// One release parsed from a changelog file
+#[derive(Debug, PartialEq)]
+struct ImportedRelease {
+    /// Version from the header
+    version: VersionName,
+    /// Date from the header, as written
+    date_text: Option<String>,
+    /// Release notes converted to the entry format
+    text: String,
+}
+
+/// Result of parsing a changelog file
+#[derive(Debug, PartialEq, Default)]
+struct ImportedChangelog {
+    /// Content of the "Unreleased" section, if any
+    unreleased: Option<String>,
+    /// Releases, newest first (as in the file)
+    releases: Vec<ImportedRelease>,
+    /// Number of non-empty lines before the first release, which were dropped
+    dropped_preamble_lines: usize,
+}
+
+/// Perform the action of importing a hand-written changelog file into the store
+pub(crate) fn cl_import(ctx: AppContext, opts: ClImport) -> anyhow::Result<()> {
+    let mut store = Store::new(&ctx, false)?;
+
+    let channel = opts
+        .channel
+        .unwrap_or_else(|| ctx.config.default_channel.clone());
+    if !ctx.config.channels.contains_key(&channel) {
+        bail!("No such channel: {channel}");
+    }
+
+    let path = ctx.root.join(&opts.file);
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Reading changelog file: {}", path.display()))?;
+
+    let config = ctx.config.for_channel(&channel);
+    let parsed = parse_changelog(&config, &content)?;
+
+    if parsed.dropped_preamble_lines > 0 {
+        eprintln!(
+            "{}",
+            format!(
+                "{} lines of text before the first release are not imported.",
+                parsed.dropped_preamble_lines
+            )
+            .yellow()
+        );
+    }
+
+    if parsed.releases.is_empty() && parsed.unreleased.is_none() {
+        bail!("No releases found in {}", path.display());
+    }
+
+    // Check everything first, so we don't leave the import half-done
+    let mut releases = vec![];
+    let mut entries = vec![];
+    for imported in parsed.releases.iter().rev() {
+        if store.version_exists(&imported.version) {
+            bail!("Version {} already exists in the store.", imported.version);
+        }
+
+        let entry_name = make_entry_name(&channel, &imported.version);
+        if store.entry_exists(&entry_name) {
+            bail!("Entry {entry_name} already exists.");
+        }
+
+        let date = imported
+            .date_text
+            .as_deref()
+            .and_then(|d| parse_date(&ctx.config, d));
+        if let Some(date_text) = &imported.date_text
+            && date.is_none()
+        {
+            eprintln!(
+                "{}",
+                format!(
+                    "Date \"{date_text}\" of release {} does not match date_format, it will be taken from the changelog file when regenerating.",
+                    imported.version
+                )
+                .yellow()
+            );
+        }
+
+        entries.push((entry_name.clone(), &imported.text));
+        releases.push(Release {
+            version: imported.version.clone(),
+            entries: vec![entry_name],
+            date,
+            ..Default::default()
+        });
+    }
+
+    let unreleased_name = make_entry_name(&channel, "unreleased");
+    if let Some(text) = &parsed.unreleased {
+        if store.entry_exists(&unreleased_name) {
+            bail!("Entry {unreleased_name} already exists.");
+        }
+        entries.push((unreleased_name, text));
+    }
+
+    // Limit the entries to this channel, so they don't show up as unreleased on others
+    let count = releases.len();
+    let mut created = vec![];
+    let mut result = Ok(());
+    for (name, text) in entries {
+        result = store.create_entry(
+            name.clone(),
+            format!("+++\nchannels = [{channel:?}]\n+++\n{text}"),
+        );
+        if result.is_err() {
+            break;
+        }
+        created.push(name);
+    }
+    let result = result.and_then(|()| store.import_releases(&channel, releases));
+
+    // Don't leave entries without their releases behind, so the import can be run again
+    if let Err(e) = result {
+        for name in &created {
+            if let Err(e) = store.remove_entry(name) {
+                log::warn!("{e:#}");
+            }
+        }
+        return Err(e);
+    }
+
+    println!(
+        "{}",
+        format!("Imported {count} releases into channel {channel}.").green()
+    );
+    if parsed.unreleased.is_some() {
+        println!("Unreleased changes were imported as an entry waiting for release.");
+    }
+    println!(
+        "Use `{} regenerate --check` to compare the changelog file with the store.",
+        ctx.binary_name
+    );
+    Ok(())
+}
+
+/// Build a name for a synthetic entry holding an imported release
+fn make_entry_name(channel: &str, version: &str) -> EntryName {
+    let version: String = version
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("imported-{channel}-{version}")
+}
+
+/// Parse a release date using the configured format.
+///
+/// Only accepted if it formats back to the same text, so that regenerating gives the same header.
+fn parse_date(config: &Config, date_text: &str) -> Option<DateTime<FixedOffset>> {
+    let date = NaiveDate::parse_from_str(date_text, &config.date_format).ok()?;
+    if date.format(&config.date_format).to_string() != date_text {
+        return None;
+    }
+    let datetime = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()?;
+    Some(datetime.fixed_offset())
+}
+
+/// Parse a release header (without the leading `## `) into version and date.
+///
+/// The configured `release_header` pattern is tried first, then the Keep-a-Changelog style `[version] - date`.
+fn parse_release_header(
+    config: &Config,
+    header: &str,
+) -> anyhow::Result<(VersionName, Option<String>)> {
+    let configured = format!(
+        "^{}$",
+        regex::escape(&config.release_header)
+            .replace(&regex::escape("{VERSION}"), "(?P<version>.+?)")
+            .replace(&regex::escape("{DATE}"), "(?P<date>.+?)")
+    );
+    let configured = regex::Regex::new(&configured).context("Building release header pattern")?;
+    let generic = regex::Regex::new(r"^\[?(?P<version>[^\]\s]+)\]?(?:\s+-\s+(?P<date>.+?))?$")?;
+
+    for pattern in [configured, generic] {
+        if let Some(caps) = pattern.captures(header)
+            && let Some(version) = caps.name("version")
+        {
+            return Ok((
+                version.as_str().to_string(),
+                caps.name("date").map(|d| d.as_str().to_string()),
+            ));
+        }
+    }
+
+    bail!("Release header not recognized: ## {header}");
+}
+
+/// Parse Keep-a-Changelog style text into releases
+fn parse_changelog(config: &Config, content: &str) -> anyhow::Result<ImportedChangelog> {
+    let content = content
+        .strip_prefix(&config.changelog_header)
+        .unwrap_or(content);
+
+    let link_definition = regex::Regex::new(r"^\[[^\]]+\]:\s*\S+")?;
+
+    let mut parsed = ImportedChangelog::default();
+    let mut current: Option<ImportedRelease> = None;
+
+    fn finish(release: Option<ImportedRelease>, parsed: &mut ImportedChangelog) {
+        if let Some(release) = release {
+            if release.version.eq_ignore_ascii_case("unreleased") {
+                if !release.text.trim().is_empty() {
+                    parsed.unreleased = Some(release.text);
+                }
+            } else {
+                parsed.releases.push(release);
+            }
+        }
+    }
+
+    for line in content.lines() {
+        let line = line.trim_end();
+        let trimmed = line.trim();
+        if trimmed.is_empty() || link_definition.is_match(trimmed) {
+            continue;
+        }
+
+        if let Some(header) = trimmed.strip_prefix("## ") {
+            finish(current.take(), &mut parsed);
+            let (version, date_text) = parse_release_header(config, header.trim())?;
+            current = Some(ImportedRelease {
+                version,
+                date_text,
+                text: String::new(),
+            });
+            continue;
+        }
+
+        let Some(release) = current.as_mut() else {
+            // Title, intro text etc.
+            if !trimmed.starts_with("# ") {
+                parsed.dropped_preamble_lines += 1;
+            }
+            continue;
+        };
+
+        if trimmed.starts_with('#') {
+            // Section heading (### Fixes) - the entry format uses any number of #
+            let section = trimmed.trim_start_matches(['#', ' ']);
+            if !release.text.is_empty() {
+                release.text.push('\n');
+            }
+            release.text.push_str(&format!("# {section}\n"));
+        } else {
+            release.text.push_str(line);
+            release.text.push('\n');
+        }
+    }
+    finish(current.take(), &mut parsed);
+
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clpack::config::ChannelConfig;
+
+    #[test]
+    fn test_parse_changelog() {
+        let config = Config::default();
+        let text = "# Changelog\n\nAll notable changes to this project will be documented in this file.\n\n## [Unreleased]\n### Added\n- Upcoming\n\n## [1.1.0] - 2019-02-15\n\n### Added\n- Feature\n  details\n\n### Fixed\n- Bug\n\n## 1.0.0\n- First\n\n[Unreleased]: https://example.com/compare/v1.1.0...HEAD\n[1.1.0]: https://example.com/compare/v1.0.0...v1.1.0\n";
+
+        let parsed = parse_changelog(&config, text).unwrap();
+
+        assert_eq!(
+            parsed,
+            ImportedChangelog {
+                unreleased: Some("# Added\n- Upcoming\n".to_string()),
+                releases: vec![
+                    ImportedRelease {
+                        version: "1.1.0".to_string(),
+                        date_text: Some("2019-02-15".to_string()),
+                        text: "# Added\n- Feature\n  details\n\n# Fixed\n- Bug\n".to_string(),
+                    },
+                    ImportedRelease {
+                        version: "1.0.0".to_string(),
+                        date_text: None,
+                        text: "- First\n".to_string(),
+                    },
+                ],
+                dropped_preamble_lines: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_release_header() {
+        let config = Config {
+            release_header: "Version {VERSION} ({DATE})".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            parse_release_header(&config, "Version 2.0 (Jan 1)").unwrap(),
+            ("2.0".to_string(), Some("Jan 1".to_string()))
+        );
+        assert_eq!(
+            parse_release_header(&config, "[2.1] - 2020-01-01").unwrap(),
+            ("2.1".to_string(), Some("2020-01-01".to_string()))
+        );
+        assert!(parse_release_header(&config, "Some text").is_err());
+    }
+
+    #[test]
+    fn test_parse_date() {
+        let config = Config::default();
+        assert!(parse_date(&config, "2019-02-15").is_some());
+        assert!(parse_date(&config, "2019-2-15").is_none());
+        assert!(parse_date(&config, "yesterday").is_none());
+    }
+
+    #[test]
+    fn test_import_then_regenerate() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("changelog")).unwrap();
+        let text = "# Changelog\n\n## [1.1.0] - 2019-02-15\n\n### Added\n- Feature\n  details\n\n### Fixed\n- Bug\n\n## [1.0.0] - 2019-01-10\n- First\n";
+        std::fs::write(dir.join("CHANGELOG.md"), text).unwrap();
+
+        cl_import(
+            AppContext::new(dir, Config::default()),
+            ClImport {
+                file: PathBuf::from("CHANGELOG.md"),
+                channel: None,
+            },
+        )
+        .unwrap();
+
+        let ctx = AppContext::new(dir, Config::default());
+        let store = Store::new(&ctx, false).unwrap();
+        assert_eq!(
+            store
+                .render_changelog(&"default".to_string(), Some(text))
+                .unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn test_import_then_flush() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("changelog")).unwrap();
+        std::fs::write(
+            dir.join("CHANGELOG.md"),
+            "# Changelog\n\n## [1.1.0] - 2019-02-15\n- Feature\n\n## [1.0.0] - 2019-01-10\n- First\n",
+        )
+        .unwrap();
+
+        // The imported entries are limited to the default channel, which must not keep them from flushing
+        let make_ctx = || {
+            let mut config = Config::default();
+            config.channels.insert(
+                "beta".to_string(),
+                ChannelConfig::Branch("/^beta\\//".to_string()),
+            );
+            AppContext::new(dir, config)
+        };
+        cl_import(
+            make_ctx(),
+            ClImport {
+                file: PathBuf::from("CHANGELOG.md"),
+                channel: None,
+            },
+        )
+        .unwrap();
+
+        let ctx = make_ctx();
+        let store = Store::new(&ctx, false).unwrap();
+        let plan = store.plan_flush();
+        assert_eq!(
+            plan.entries,
+            vec![
+                make_entry_name("default", "1.0.0"),
+                make_entry_name("default", "1.1.0"),
+            ]
+        );
+        assert_eq!(plan.releases["default"], vec!["1.0.0", "1.1.0"]);
+    }
+}
@@ -1,5 +1,5 @@
-use crate::config::Config;
-use crate::store::Store;
+use clpack::config::Config;
+use clpack::store::Store;
 use colored::Colorize;
 use std::fs::OpenOptions;
 use std::io::Write;
@@ -22,14 +22,14 @@ pub fn cl_init(opts: ClInit) -> anyhow::Result<()> {
     if !opts.config_path.exists() {
         let mut file = OpenOptions::new()
             .write(true)
-            .create(true)
+            .create_new(true)
             .open(&opts.config_path)?;
 
         println!(
             "Creating clpack config file: {}",
             opts.config_path.display()
         );
-        file.write_all(crate::config::CONFIG_FILE_TEMPLATE.as_bytes())?;
+        file.write_all(clpack::config::CONFIG_FILE_TEMPLATE.as_bytes())?;
     } else {
         println!(
             "Loading existing config file: {}",
@@ -39,10 +39,12 @@ pub fn cl_init(opts: ClInit) -> anyhow::Result<()> {
         default_config = toml::from_str(&file_text)?;
     }
 
-    let ctx = crate::AppContext {
+    let ctx = clpack::AppContext {
         binary_name: opts.binary_name,
         config: default_config,
         root: opts.root,
+        branch: None,
+        package: None,
     };
     let _ = Store::new(&ctx, true)?;
 
@@ -1,12 +1,39 @@
-use crate::AppContext;
-use crate::git::BranchOpt;
-use crate::git::get_branch_name;
-use crate::store::Store;
-use anyhow::bail;
+use crate::ui;
+use anyhow::{Context, bail};
+use clpack::AppContext;
+use clpack::config::PackageName;
+use clpack::entry::Entry;
+use clpack::git::BranchOpt;
+use clpack::git::get_branch_name;
+use clpack::integrations::{EntryEvent, Registry};
+use clpack::package::changed_packages;
+use clpack::store::Store;
 use colored::Colorize;
+use indexmap::IndexMap;
+use std::ffi::OsStr;
+use std::io::Read;
+
+/// Args for cl_log(), all optional - missing values are asked for interactively
+#[derive(Debug, Default)]
+pub struct ClLog {
+    /// Entry name (file name without extension)
+    pub name: Option<String>,
+    /// Messages grouped by section, in the order given on the command line.
+    /// Messages given before any section go to the unnamed section (empty key).
+    pub sections: IndexMap<String, Vec<String>>,
+    /// Read the whole entry body from stdin
+    pub stdin: bool,
+}
+
+impl ClLog {
+    /// Content was given by flags, so the editor is not needed
+    fn has_content(&self) -> bool {
+        self.stdin || self.sections.values().any(|msgs| !msgs.is_empty())
+    }
+}
 
 /// Perform the action of adding a new log entry
-pub(crate) fn cl_log(ctx: AppContext) -> anyhow::Result<()> {
+pub(crate) fn cl_log(ctx: AppContext, opts: ClLog) -> anyhow::Result<()> {
     let store = Store::new(&ctx, false)?;
 
     let branch = get_branch_name(&ctx);
@@ -29,80 +56,353 @@ pub(crate) fn cl_log(ctx: AppContext) -> anyhow::Result<()> {
         );
     }
 
-    let mut entry_name = branch.as_str_or_default().to_string();
+    // Sections of the channel of this branch, if it has its own
+    let channel = branch
+        .as_ref()
+        .map(|b| b.parse_channel(&ctx.config))
+        .transpose()?
+        .flatten();
+    let known_sections = match &channel {
+        Some(channel) => ctx.config.for_channel(channel).sections.clone(),
+        None => ctx.config.sections.clone(),
+    };
+    for section in opts.sections.keys().filter(|s| !s.is_empty()) {
+        if !known_sections.contains(section) {
+            bail!(
+                "Unknown section \"{section}\", expected one of: {}",
+                known_sections.join(", ")
+            );
+        }
+    }
 
-    // Space
-    println!();
+    // Packages of a monorepo - an entry of one package goes to its subfolder, more are listed in the front-matter
+    let packages = if ctx.config.packages.is_empty() {
+        vec![]
+    } else {
+        resolve_entry_packages(&ctx, ui::is_interactive() && !opts.has_content())?
+    };
+    let in_folder = |name: &str| match packages.as_slice() {
+        [package] => format!("{package}/{name}"),
+        _ => name.to_string(),
+    };
 
-    loop {
-        // Ask for filename
-        let mut query = inquire::Text::new("Log entry name:")
-            .with_help_message("Used as a filename, without extension");
-        if issue.is_some() {
-            query = query.with_initial_value(&entry_name);
+    let entry_name = if let Some(name) = opts.name.clone() {
+        store.check_entry_name(&in_folder(&name))?;
+        if store.entry_exists(&in_folder(&name)) {
+            bail!("Entry \"{name}\" already exists, use a different name.");
         }
-        entry_name = query.prompt()?;
+        name
+    } else if opts.has_content() {
+        // Scripted use - take the name from the branch, like the interactive prompt would suggest
+        let name = entry_name_from_branch(branch.as_str_or_default());
+        if name.is_empty() {
+            bail!("Entry name could not be taken from the git branch, use --name.");
+        }
+        if store.entry_exists(&in_folder(&name)) {
+            bail!("Entry \"{name}\" already exists, use --name to choose a different one.");
+        }
+        name
+    } else {
+        ui::ensure_interactive("the entry name")?;
 
-        if entry_name.is_empty() {
-            bail!("Cancelled");
+        let mut entry_name = entry_name_from_branch(branch.as_str_or_default());
+
+        // Space
+        println!();
+
+        loop {
+            // Ask for filename
+            let initial = if issue.is_some() { &entry_name } else { "" };
+            entry_name = ui::text(
+                "Log entry name:",
+                initial,
+                Some("Used as a filename, without extension"),
+            )?;
+
+            if entry_name.is_empty() {
+                bail!("Cancelled");
+            }
+
+            if let Err(e) = store.check_entry_name(&in_folder(&entry_name)) {
+                println!("{}", e.to_string().red());
+            } else if store.entry_exists(&in_folder(&entry_name)) {
+                println!("{}", "Entry already exists, try different name.".red());
+            } else {
+                break;
+            }
         }
+        entry_name
+    };
 
-        if store.entry_exists(&entry_name) {
-            println!("{}", "Entry already exists, try different name.".red());
+    let mut text = if opts.stdin {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Reading entry from stdin")?;
+        if text.trim().is_empty() {
+            bail!("Entry read from stdin is empty");
+        }
+        match issue.as_deref() {
+            Some(num) => add_issue_references(&text, num),
+            None => text,
+        }
+    } else if opts.has_content() {
+        render_messages(&opts.sections, issue.as_deref())
+    } else {
+        let sections = if opts.sections.is_empty() {
+            ui::ensure_interactive("changelog sections")?;
+
+            // Space
+            println!();
+
+            // Ask for sections
+            ui::multi_select(
+                "Choose changelog sections to pre-generate (at least one)",
+                known_sections,
+                &[],
+            )?
         } else {
-            break;
+            opts.sections.keys().cloned().collect()
+        };
+
+        if sections.is_empty() {
+            bail!("Cancelled");
         }
+
+        ui::ensure_interactive("the entry text")?;
+
+        let mut prefill_text = String::new();
+
+        for section in sections {
+            if !prefill_text.is_empty() {
+                prefill_text.push('\n');
+            }
+            prefill_text.push_str(&format!("# {section}\n"));
+            if let Some(num) = &issue {
+                prefill_text.push_str(&format!("-  (#{num})\n"));
+            } else {
+                prefill_text.push_str("- \n");
+            }
+        }
+
+        println!(
+            "\nPreview of changelog entry \"{entry_name}\" (not yet saved)\n\n{}\n",
+            prefill_text
+        );
+
+        // Configured editor command, possibly with arguments (e.g. `code --wait`)
+        let editor_command: Vec<&OsStr> = ctx
+            .config
+            .editor
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(OsStr::new)
+            .collect();
+
+        // Edit the file
+        let text = ui::edit(
+            "Edit as needed, then confirm",
+            &prefill_text,
+            "md",
+            &editor_command,
+        )?;
+
+        if text.is_empty() { prefill_text } else { text }
+    };
+
+    if !text.ends_with('\n') {
+        text.push('\n');
     }
 
-    // Space
-    println!();
+    if packages.len() > 1 {
+        text = add_packages_front_matter(&text, &packages);
+    }
 
-    // Ask for sections
-    let sections = inquire::MultiSelect::new(
-        "Choose changelog sections to pre-generate (at least one)",
-        ctx.config.sections.clone(),
-    )
-    .prompt()?;
+    let entry_name = in_folder(&entry_name);
+    store.create_entry(entry_name.clone(), text.clone())?;
+    notify_entry_created(&ctx, &entry_name, &text)?;
 
-    if sections.is_empty() {
+    println!("{}", "Done.".green());
+    Ok(())
+}
+
+/// Choose the packages a new entry belongs to - the selected package, or the ones changed on the branch.
+/// Those are only pre-selected if asking is allowed.
+pub fn resolve_entry_packages(
+    ctx: &AppContext,
+    interactive: bool,
+) -> anyhow::Result<Vec<PackageName>> {
+    if let Some(package) = &ctx.package {
+        return Ok(vec![package.clone()]);
+    }
+
+    let changed = changed_packages(ctx).unwrap_or_default();
+    if !interactive {
+        if changed.is_empty() {
+            bail!(
+                "Package could not be detected from the current directory or the changes on the branch, use --package."
+            );
+        }
+        return Ok(changed);
+    }
+
+    let packages = ctx.config.packages.keys().collect::<Vec<_>>();
+    let defaults = changed
+        .iter()
+        .filter_map(|name| ctx.config.packages.get_index_of(name))
+        .collect::<Vec<_>>();
+    let chosen = ui::multi_select("Packages affected by the change?", packages, &defaults)?;
+    if chosen.is_empty() {
         bail!("Cancelled");
     }
+    Ok(chosen.into_iter().cloned().collect())
+}
+
+/// Let the integrations know about a new entry
+pub(crate) fn notify_entry_created(ctx: &AppContext, name: &str, text: &str) -> anyhow::Result<()> {
+    let entry = Entry::parse(text)?;
+    Registry::from_config(&ctx.config.integrations).entry_created(&EntryEvent {
+        config: &ctx.config,
+        name,
+        entry: &entry,
+    })
+}
+
+/// Suggest an entry name from a branch name - path separators of e.g. `feature/login` would make it a subfolder
+fn entry_name_from_branch(branch: &str) -> String {
+    branch.replace(['/', '\\'], "-")
+}
+
+/// Add the `packages` key to the front-matter of entry text, adding the front-matter block if there is none
+fn add_packages_front_matter(text: &str, packages: &[PackageName]) -> String {
+    let line = format!("packages = {}\n", toml::Value::from(packages.to_vec()));
+    match text.strip_prefix("+++\n") {
+        Some(rest) => format!("+++\n{line}{rest}"),
+        None => format!("+++\n{line}+++\n{text}"),
+    }
+}
+
+/// Build entry text from messages given on the command line, one bullet per message.
+/// The issue number is appended the same way as in the interactive template.
+fn render_messages(sections: &IndexMap<String, Vec<String>>, issue: Option<&str>) -> String {
+    let mut text = String::new();
+
+    for (section, messages) in sections {
+        if messages.is_empty() {
+            continue;
+        }
+        if !text.is_empty() {
+            text.push('\n');
+        }
+        if !section.is_empty() {
+            text.push_str(&format!("# {section}\n"));
+        }
+        for message in messages {
+            let message = message.trim().trim_start_matches("- ");
+            match issue {
+                Some(num) if !mentions_issue(message, num) => {
+                    text.push_str(&format!("- {message} (#{num})\n"));
+                }
+                _ => {
+                    text.push_str(&format!("- {message}\n"));
+                }
+            }
+        }
+    }
 
-    let mut prefill_text = String::new();
+    text
+}
 
-    for section in sections {
-        if !prefill_text.is_empty() {
-            prefill_text.push('\n');
+/// Append the issue number to the bullets of entry text that don't mention it yet, like `render_messages()` does
+fn add_issue_references(text: &str, issue: &str) -> String {
+    let mut in_front_matter = false;
+    let mut result = String::new();
+    for line in text.lines() {
+        if line == "+++" {
+            in_front_matter = !in_front_matter;
         }
-        prefill_text.push_str(&format!("# {section}\n"));
-        if let Some(num) = &issue {
-            prefill_text.push_str(&format!("-  (#{num})\n"));
+        let line = line.trim_end();
+        if !in_front_matter && line.starts_with("- ") && !mentions_issue(line, issue) {
+            result.push_str(&format!("{line} (#{issue})\n"));
         } else {
-            prefill_text.push_str("- \n");
+            result.push_str(line);
+            result.push('\n');
         }
     }
+    result
+}
 
-    println!(
-        "\nPreview of changelog entry \"{entry_name}\" (not yet saved)\n\n{}\n",
-        prefill_text
-    );
+/// Check if a message references the issue number, e.g. `#12` but not `#123`
+fn mentions_issue(message: &str, issue: &str) -> bool {
+    let reference = format!("#{issue}");
+    message.match_indices(&reference).any(|(pos, _)| {
+        !message[pos + reference.len()..].starts_with(|c: char| c.is_alphanumeric())
+    })
+}
 
-    // Edit the file
-    let mut text = inquire::Editor::new("Edit as needed, then confirm")
-        .with_predefined_text(&prefill_text)
-        .with_file_extension("md")
-        .prompt()?;
+#[cfg(test)]
+mod test {
+    use super::*;
 
-    if text.is_empty() {
-        text = prefill_text;
+    #[test]
+    fn test_add_packages_front_matter() {
+        let packages = vec!["core".to_string(), "app".to_string()];
+        assert_eq!(
+            add_packages_front_matter("# Fixes\n- fix\n", &packages),
+            "+++\npackages = [\"core\", \"app\"]\n+++\n# Fixes\n- fix\n"
+        );
+        assert_eq!(
+            add_packages_front_matter("+++\ninternal = true\n+++\n- fix\n", &packages),
+            "+++\npackages = [\"core\", \"app\"]\ninternal = true\n+++\n- fix\n"
+        );
     }
 
-    if !text.ends_with('\n') {
-        text.push('\n');
+    #[test]
+    fn test_entry_name_from_branch() {
+        assert_eq!(entry_name_from_branch("feature/login"), "feature-login");
+        assert_eq!(entry_name_from_branch("fix\\12-crash"), "fix-12-crash");
+        assert_eq!(entry_name_from_branch("12-crash"), "12-crash");
+    }
+
+    #[test]
+    fn test_add_issue_references() {
+        assert_eq!(
+            add_issue_references(
+                "+++\ninternal = true\n+++\n# Fixes\n- Fix crash\n- Fix leak (#12)\n- Fix #123\n",
+                "12"
+            ),
+            "+++\ninternal = true\n+++\n# Fixes\n- Fix crash (#12)\n- Fix leak (#12)\n- Fix #123 (#12)\n"
+        );
     }
 
-    store.create_entry(entry_name, text)?;
+    #[test]
+    fn test_mentions_issue() {
+        assert!(mentions_issue("Fix leak (#12)", "12"));
+        assert!(mentions_issue("#12: fix leak", "12"));
+        assert!(!mentions_issue("Fix leak (#123)", "12"));
+        assert!(!mentions_issue("Fix leak", "12"));
+    }
 
-    println!("{}", "Done.".green());
-    Ok(())
+    #[test]
+    fn test_render_messages() {
+        let sections = IndexMap::from([
+            ("".to_string(), vec!["Loose line".to_string()]),
+            (
+                "Fixes".to_string(),
+                vec!["Fix crash".to_string(), "- Fix leak (#12)".to_string()],
+            ),
+            ("Internal".to_string(), vec![]),
+        ]);
+
+        assert_eq!(
+            render_messages(&sections, Some("12")),
+            "- Loose line (#12)\n\n# Fixes\n- Fix crash (#12)\n- Fix leak (#12)\n"
+        );
+
+        assert_eq!(
+            render_messages(&sections, None),
+            "- Loose line\n\n# Fixes\n- Fix crash\n- Fix leak (#12)\n"
+        );
+    }
 }
@@ -1,20 +1,43 @@
-use crate::AppContext;
-use crate::config::ChannelName;
-use crate::git::{BranchName, get_branch_name};
-use crate::integrations::youtrack::{
-    youtrack_integration_enabled, youtrack_integration_on_release,
+use crate::ui;
+use crate::{EXIT_NOTHING_TO_RELEASE, EXIT_VERSION_EXISTS, ExitCodeError};
+use anyhow::{Context, bail};
+use clpack::AppContext;
+use clpack::config::{ChannelName, VersionName};
+use clpack::git::{
+    BranchName, check_git_usable, commit_files, create_annotated_tag, fill_git_template,
+    get_branch_name, get_head_commit, push_tag, tag_exists,
+};
+use clpack::integrations::{Integration, KNOWN_INTEGRATIONS, Registry, ReleaseEvent};
+use clpack::store::{Release, Store};
+use clpack::version::{
+    SemVer, VERSION_AUTO, VersionUpdate, bump_level, find_project_version, plan_version_updates,
 };
-use crate::store::{Release, Store};
-use anyhow::bail;
 use colored::Colorize;
+use indexmap::IndexMap;
+
+/// Args for cl_pack()
+#[derive(Debug, Default)]
+pub struct ClPack {
+    /// Channel given on the command line
+    pub channel: Option<ChannelName>,
+    /// Version given on the command line or in the environment - skips the version prompt
+    pub version: Option<VersionName>,
+    /// Do not ask for confirmations (scripted use)
+    pub yes: bool,
+    /// Explicit choices to run (true) or skip (false) integrations, by ID. Others are asked about, or run if `yes`.
+    pub integrations: IndexMap<String, bool>,
+    /// Only show what would be written
+    pub dry_run: bool,
+}
 
 pub fn pack_resolve_and_show_preview(
     ctx: &AppContext,
     user_chosen_channel: Option<ChannelName>,
     branch: Option<&BranchName>,
+    interactive: bool,
 ) -> anyhow::Result<Option<(Release, ChannelName)>> {
-    let channel = resolve_channel(&ctx, user_chosen_channel, branch)?;
-    let store = Store::new(&ctx, false)?;
+    let channel = resolve_channel(ctx, user_chosen_channel, branch, interactive)?;
+    let store = Store::new(ctx, false)?;
 
     let unreleased = store.find_unreleased_changes(&channel)?;
 
@@ -33,20 +56,22 @@ pub fn pack_resolve_and_show_preview(
     let release = Release {
         version: "Unreleased".to_string(),
         entries: unreleased,
+        ..Default::default()
     };
 
-    let rendered = store.render_release(&release)?;
+    let rendered = store.render_release(&channel, &release)?;
 
     println!("\nPreview:\n\n{}", rendered);
 
     Ok(Some((release, channel)))
 }
 
-/// Resolve channel from current branch or other context info, ask if needed
-fn resolve_channel(
+/// Resolve channel from current branch or other context info, ask if needed (and allowed)
+pub(crate) fn resolve_channel(
     ctx: &AppContext,
     user_chosen_channel: Option<ChannelName>,
     branch: Option<&BranchName>,
+    interactive: bool,
 ) -> anyhow::Result<ChannelName> {
     let (channel_detected, channel_explicit) = match user_chosen_channel {
         Some(ch) => (Some(ch), true), // passed via flag already
@@ -70,17 +95,18 @@ fn resolve_channel(
     let channel = if ctx.config.channels.len() > 1 {
         if channel_explicit {
             channel_detected.unwrap()
+        } else if !interactive {
+            let Some(channel) = channel_detected else {
+                bail!("Release channel could not be detected from the branch, use --channel.");
+            };
+            channel
         } else {
             let channels = ctx.config.channels.keys().collect::<Vec<_>>();
             let mut starting_index = None;
             if let Some(channel) = channel_detected {
                 starting_index = channels.iter().position(|ch| *ch == &channel);
             }
-            let mut query = inquire::Select::new("Release channel?", channels);
-            if let Some(index) = starting_index {
-                query = query.with_starting_cursor(index);
-            }
-            query.prompt()?.to_string()
+            ui::select("Release channel?", channels, starting_index)?.to_string()
         }
     } else {
         // Just one channel, so use that
@@ -91,54 +117,295 @@ fn resolve_channel(
     Ok(channel)
 }
 
+/// Suggest the next version by bumping the last release of the channel (or of the default channel,
+/// if there is none yet) according to the unreleased entries
+fn suggest_bumped_version(
+    ctx: &AppContext,
+    store: &Store,
+    channel: &ChannelName,
+    release: &Release,
+) -> anyhow::Result<Option<VersionName>> {
+    let Some(last) = store
+        .last_release(channel)
+        .or_else(|| store.last_release(&ctx.config.default_channel))
+    else {
+        return Ok(None);
+    };
+    let Some(last_version) = SemVer::parse(&last.version) else {
+        return Ok(None);
+    };
+
+    let entries = store.load_release_entries(release)?;
+    let Some(level) = bump_level(&ctx.config.bump, entries.values()) else {
+        return Ok(None);
+    };
+
+    let pre_id = ctx.config.bump.prerelease.get(channel).map(|s| s.as_str());
+    let version = last_version.bump(level, pre_id).to_string();
+    if store.version_exists(&version) {
+        return Ok(None);
+    }
+
+    println!(
+        "Suggested version: {} ({level} bump from {})",
+        version.green(),
+        last.version
+    );
+    Ok(Some(version))
+}
+
+/// Integrations to run for a release on the channel, without those skipped on the command line.
+/// Integrations requested on the command line must be enabled for the channel.
+fn select_integrations<'r>(
+    registry: &'r Registry,
+    channel: &str,
+    choices: &IndexMap<String, bool>,
+) -> anyhow::Result<Vec<&'r dyn Integration>> {
+    for (id, run) in choices {
+        let Some((_, compiled_in)) = KNOWN_INTEGRATIONS.iter().find(|(known, _)| known == id)
+        else {
+            let known = KNOWN_INTEGRATIONS
+                .iter()
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+            bail!(
+                "No such integration: {id}. Known integrations: {}",
+                known.join(", ")
+            );
+        };
+        if !run {
+            continue;
+        }
+        if !compiled_in {
+            bail!(
+                "Integration {id} was requested, but clpack was built without the `{id}` feature."
+            );
+        }
+        if !registry.get(id).is_some_and(|i| i.runs_on(channel)) {
+            bail!(
+                "Integration {id} was requested, but it is not enabled and configured for channel {channel}."
+            );
+        }
+    }
+
+    for id in registry.compiled_out() {
+        if choices.get(*id) != Some(&false) {
+            eprintln!(
+                "{}",
+                format!("Integration {id} is enabled, but clpack was built without the `{id}` feature - skipped.")
+                    .yellow()
+            );
+        }
+    }
+
+    Ok(registry
+        .for_channel(channel)
+        .filter(|i| choices.get(i.id()) != Some(&false))
+        .collect())
+}
+
+/// Show what the integrations will do and ask which ones to run (if allowed), unless chosen on the command line.
+/// The chosen ones then check the release before anything is written.
+fn confirm_integrations<'r>(
+    integrations: Vec<&'r dyn Integration>,
+    event: &ReleaseEvent,
+    choices: &IndexMap<String, bool>,
+    ask: bool,
+) -> anyhow::Result<Vec<&'r dyn Integration>> {
+    let mut confirmed = vec![];
+    for integration in integrations {
+        println!("{}:", integration.name().bold());
+        for line in integration.describe_release(event)? {
+            println!("- {line}");
+        }
+
+        let run = match choices.get(integration.id()) {
+            Some(run) => *run,
+            None if !ask => true,
+            None => ui::confirm(
+                &format!(
+                    "Run the {} integration for this release?",
+                    integration.name()
+                ),
+                true,
+            )?,
+        };
+
+        if run {
+            integration.on_before_release(event)?;
+            confirmed.push(integration);
+        } else {
+            eprintln!("{}", format!("{} skipped.", integration.name()).yellow());
+        }
+    }
+    Ok(confirmed)
+}
+
+/// Print the lines changed by a version update
+fn print_version_update(update: &VersionUpdate) {
+    println!("{}:", update.file.cyan());
+    for (old, new) in update.old_content.lines().zip(update.new_content.lines()) {
+        if old != new {
+            println!("{}", format!("- {old}").red());
+            println!("{}", format!("+ {new}").green());
+        }
+    }
+}
+
 /// Perform the action of packing changelog entries for a release
-pub(crate) fn cl_pack(
-    ctx: AppContext,
-    user_chosen_channel: Option<ChannelName>,
-) -> anyhow::Result<()> {
+pub(crate) fn cl_pack(ctx: AppContext, opts: ClPack) -> anyhow::Result<()> {
     let branch = get_branch_name(&ctx);
     let Some((mut release, channel)) =
-        pack_resolve_and_show_preview(&ctx, user_chosen_channel, branch.as_ref())?
+        pack_resolve_and_show_preview(&ctx, opts.channel, branch.as_ref(), !opts.yes)?
     else {
-        // No changes
+        // No changes - scripted runs get a distinct exit code
+        if opts.yes || opts.version.is_some() {
+            return Err(ExitCodeError::new(EXIT_NOTHING_TO_RELEASE, "Nothing to release.").into());
+        }
         return Ok(());
     };
 
     let mut store = Store::new(&ctx, false)?;
 
-    // If the branch is named rel/3.40, this can extract 3.40.
-    // TODO try to get something better from git!
-    let version_base = branch
-        .as_ref()
-        .map(|b| b.parse_version(&ctx.config))
-        .transpose()?
-        .flatten();
-
-    // Ask for the version
-    let mut version = version_base.unwrap_or_default();
-    loop {
-        // Ask for full version
-        version = inquire::Text::new("Version:")
-            .with_initial_value(&version)
-            .prompt()?;
+    let registry = Registry::from_config(&ctx.config.integrations);
+    let integrations = select_integrations(&registry, &channel, &opts.integrations)?;
 
-        if version.is_empty() {
-            bail!("Cancelled");
+    let version = if let Some(version) = opts.version {
+        let version = if version == VERSION_AUTO {
+            let Some((version, file)) = find_project_version(&ctx)? else {
+                bail!("Version could not be read from any of the configured version sources.");
+            };
+            println!("Version read from {file}");
+            version
+        } else {
+            version
+        };
+        if store.version_exists(&version) {
+            return Err(ExitCodeError::new(
+                EXIT_VERSION_EXISTS,
+                format!("Version {version} already exists."),
+            )
+            .into());
         }
+        println!("Version: {}", version.green().bold());
+        version
+    } else if opts.yes {
+        bail!("Version is required when not asking questions, use --version.");
+    } else {
+        // Version from Cargo.toml etc., unless it was already released - then it was not bumped yet
+        let project_version =
+            find_project_version(&ctx)?.filter(|(version, _)| !store.version_exists(version));
 
-        if store.version_exists(&version) {
-            println!("{}", "Version already exists, try again or cancel.".red());
+        let version_base = if let Some((version, file)) = project_version {
+            println!("Version read from {file}");
+            Some(version)
         } else {
-            break;
+            match suggest_bumped_version(&ctx, &store, &channel, &release)? {
+                Some(version) => Some(version),
+                // If the branch is named rel/3.40, this can extract 3.40.
+                None => branch
+                    .as_ref()
+                    .map(|b| b.parse_version(&ctx.config.for_channel(&channel)))
+                    .transpose()?
+                    .flatten(),
+            }
+        };
+
+        // Ask for the version
+        let mut version = version_base.unwrap_or_default();
+        loop {
+            // Ask for full version
+            version = ui::text("Version:", &version, None)?;
+
+            if version.is_empty() {
+                bail!("Cancelled");
+            }
+
+            if store.version_exists(&version) {
+                println!("{}", "Version already exists, try again or cancel.".red());
+            } else {
+                break;
+            }
+        }
+        version
+    };
+
+    release.version = version;
+    release.set_metadata(&ctx);
+
+    // Prepared before writing the release, so a broken target does not leave it half-done
+    let version_updates = plan_version_updates(&ctx, &release.version)?;
+    if !version_updates.is_empty() {
+        println!("\nVersion updates:\n");
+        for update in &version_updates {
+            print_version_update(update);
         }
+        println!();
     }
 
-    release.version = version.clone();
+    // Git commit and tag
+    let git_config = &ctx.config.git;
+    let git_enabled = (git_config.commit || git_config.tag)
+        && (git_config.channels.is_empty() || git_config.channels.contains(&channel));
+    let commit_message = fill_git_template(
+        &git_config.commit_message,
+        &release.version,
+        &channel,
+        ctx.package.as_deref(),
+    );
+    let tag_name = fill_git_template(
+        &git_config.tag_name,
+        &release.version,
+        &channel,
+        ctx.package.as_deref(),
+    );
+    if git_enabled {
+        if git_config.tag && tag_exists(&ctx, &tag_name)? {
+            bail!("Git tag {tag_name} already exists.");
+        }
+        if git_config.commit {
+            println!("Git commit: {}", commit_message.cyan());
+        }
+        if git_config.tag {
+            println!("Git tag: {}", tag_name.cyan());
+            if git_config.push {
+                println!("Git push to: {}", git_config.remote.cyan());
+            }
+        }
+    }
+    // Asked before anything is written - integrations need to know if the release gets tagged
+    let git_confirmed = git_enabled
+        && (opts.yes || opts.dry_run || ui::confirm("Commit and tag the release in git?", true)?);
+    let push_remote = (git_config.tag && git_config.push).then_some(git_config.remote.as_str());
+    if git_confirmed {
+        check_git_usable(&ctx, push_remote)?;
+    }
 
-    if !inquire::Confirm::new("Continue - write to changelog file?")
-        .with_default(true)
-        .prompt()?
-    {
+    // Integrations are confirmed before anything is written
+    let entries = store.load_release_entries(&release)?;
+    let notes = store.render_release(&channel, &release)?;
+    let event = ReleaseEvent {
+        config: &ctx.config,
+        channel: &channel,
+        package: ctx.package.as_deref(),
+        release: &release,
+        entries: &entries,
+        notes: notes.trim(),
+        tagged: git_confirmed && git_config.tag,
+    };
+    let integrations = confirm_integrations(
+        integrations,
+        &event,
+        &opts.integrations,
+        !opts.yes && !opts.dry_run,
+    )?;
+
+    if opts.dry_run {
+        println!("{}", "Dry run, nothing was written.".yellow());
+        return Ok(());
+    }
+
+    if !opts.yes && !ui::confirm("Continue - write to changelog file?", true)? {
         eprintln!("{}", "Cancelled.".red());
         return Ok(());
     }
@@ -147,19 +414,181 @@ pub(crate) fn cl_pack(
 
     println!("{}", "Changelog written.".green());
 
-    // YouTrack
-    if youtrack_integration_enabled(&ctx.config, &channel) {
-        if inquire::Confirm::new("Update released issues in YouTrack?")
-            .with_default(true)
-            .prompt()?
-        {
-            youtrack_integration_on_release(&ctx.config, release)?;
-            println!("{}", "YouTrack updated.".green());
-        } else {
-            eprintln!("{}", "YouTrack changes skipped.".yellow());
-            return Ok(());
+    for update in &version_updates {
+        update.apply()?;
+        println!("{}", format!("Version written to {}", update.file).green());
+    }
+
+    if git_confirmed {
+        if git_config.commit {
+            let files: Vec<String> = store
+                .release_files(&channel, &release)
+                .into_iter()
+                .chain(version_updates.iter().map(|u| u.path.clone()))
+                .filter(|path| path.exists())
+                .map(|path| path.to_string_lossy().into_owned())
+                .collect();
+            let files: Vec<&str> = files.iter().map(|f| f.as_str()).collect();
+            commit_files(&ctx, &files, &commit_message).with_context(|| {
+                format!(
+                    "The release was written, but not committed. Commit and tag it by hand, files:\n{}",
+                    files.join("\n")
+                )
+            })?;
+            println!("{}", format!("Committed: {commit_message}").green());
         }
+        if git_config.tag {
+            let notes = store.render_release(&channel, &release)?;
+            create_annotated_tag(&ctx, &tag_name, notes.trim()).with_context(|| {
+                format!("The release was written, but the tag {tag_name} was not created. Tag it by hand.")
+            })?;
+            println!("{}", format!("Tagged: {tag_name}").green());
+        }
+        if let Some(remote) = push_remote {
+            push_tag(&ctx, remote, &tag_name).with_context(|| {
+                format!("The release was tagged, but the tag {tag_name} was not pushed. Push it by hand.")
+            })?;
+            println!("{}", format!("Pushed: {tag_name} to {remote}").green());
+        }
+    }
+
+    // Integrations get the release commit, if one was made
+    if git_confirmed && git_config.commit {
+        release.commit = get_head_commit(&ctx);
+    }
+    let event = ReleaseEvent {
+        config: &ctx.config,
+        channel: &channel,
+        package: ctx.package.as_deref(),
+        release: &release,
+        entries: &entries,
+        notes: notes.trim(),
+        tagged: git_confirmed && git_config.tag,
+    };
+    for integration in integrations {
+        integration.on_release(&event)?;
+        println!("{}", format!("{} updated.", integration.name()).green());
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::{git, init_repo};
+    use clpack::Config;
+
+    /// Config committing and tagging releases
+    fn git_config() -> Config {
+        let mut config = Config::default();
+        config.git.commit = true;
+        config.git.tag = true;
+        config
+    }
+
+    /// Options of a scripted release
+    fn release_opts(version: &str) -> ClPack {
+        ClPack {
+            version: Some(version.to_string()),
+            yes: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_commit_and_tag() {
+        let tmp = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        init_repo(dir);
+        git(remote.path(), &["init", "-q", "--bare"]);
+        git(
+            dir,
+            &["remote", "add", "origin", &remote.path().to_string_lossy()],
+        );
+
+        let mut config = git_config();
+        config.git.push = true;
+        let ctx = AppContext::new(dir, config);
+        Store::new(&ctx, true)
+            .unwrap()
+            .create_entry("login".to_string(), "# Fixes\n- Login\n".to_string())
+            .unwrap();
+        git(dir, &["add", "-A"]);
+        git(dir, &["commit", "-q", "-m", "Login"]);
+
+        // Unrelated work in progress is left alone
+        std::fs::write(dir.join("README.md"), "Changed\n").unwrap();
+
+        cl_pack(ctx, release_opts("1.0.0")).unwrap();
+
+        assert_eq!(git(dir, &["log", "-1", "--format=%s"]), "Release 1.0.0\n");
+        assert!(
+            git(dir, &["tag", "-l", "-n9", "v1.0.0"]).contains("- Login"),
+            "tag message has the release notes"
+        );
+        assert_eq!(git(dir, &["status", "--porcelain"]), " M README.md\n");
+        assert_eq!(git(remote.path(), &["tag", "-l"]), "v1.0.0\n");
+        assert_eq!(
+            git(dir, &["show", "--name-only", "--format=", "HEAD"]),
+            "CHANGELOG.md\nchangelog/channels/default.json\n"
+        );
+    }
+
+    #[test]
+    fn test_git_checked_before_writing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        // Not a git repository
+
+        let ctx = AppContext::new(dir, git_config());
+        Store::new(&ctx, true)
+            .unwrap()
+            .create_entry("login".to_string(), "# Fixes\n- Login\n".to_string())
+            .unwrap();
+
+        assert!(cl_pack(ctx, release_opts("1.0.0")).is_err());
+        assert!(!dir.join("CHANGELOG.md").exists());
+
+        let ctx = AppContext::new(dir, Config::default());
+        assert!(!Store::new(&ctx, false).unwrap().version_exists("1.0.0"));
+    }
+
+    #[test]
+    fn test_nothing_to_release_exit_code() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        std::fs::create_dir_all(dir.join("changelog")).unwrap();
+
+        let opts = || ClPack {
+            channel: None,
+            version: None,
+            yes: false,
+            integrations: IndexMap::new(),
+            dry_run: false,
+        };
+        let exit_code = |opts: ClPack| {
+            cl_pack(AppContext::new(dir, Config::default()), opts)
+                .err()
+                .map(|e| e.downcast_ref::<ExitCodeError>().unwrap().code)
+        };
+
+        // Interactive use
+        assert_eq!(exit_code(opts()), None);
+        assert_eq!(
+            exit_code(ClPack {
+                yes: true,
+                ..opts()
+            }),
+            Some(EXIT_NOTHING_TO_RELEASE)
+        );
+        assert_eq!(
+            exit_code(ClPack {
+                version: Some("1.2.3".to_string()),
+                ..opts()
+            }),
+            Some(EXIT_NOTHING_TO_RELEASE)
+        );
+    }
+}
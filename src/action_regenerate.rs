@@ -0,0 +1,108 @@
+use anyhow::{Context, bail};
+use clpack::AppContext;
+use clpack::config::ChannelName;
+use clpack::store::Store;
+use colored::Colorize;
+
+/// Perform the action of rebuilding changelog files from the store
+pub(crate) fn cl_regenerate(
+    ctx: AppContext,
+    user_chosen_channel: Option<ChannelName>,
+    check: bool,
+) -> anyhow::Result<()> {
+    let store = Store::new(&ctx, false)?;
+
+    let channels: Vec<ChannelName> = match user_chosen_channel {
+        Some(ch) => {
+            if !ctx.config.channels.contains_key(&ch) {
+                bail!("No such channel: {ch}");
+            }
+            vec![ch]
+        }
+        None => ctx.config.channels.keys().cloned().collect(),
+    };
+
+    let mut drifted = vec![];
+    for channel in channels {
+        if !store.has_releases(&channel) {
+            eprintln!("Channel {channel} has no releases, skipped.");
+            continue;
+        }
+
+        let path = store.changelog_file_path(&channel);
+        let old_content = if path.exists() {
+            Some(
+                std::fs::read_to_string(&path)
+                    .with_context(|| format!("Reading changelog file: {}", path.display()))?,
+            )
+        } else {
+            None
+        };
+
+        let content = store.render_changelog(&channel, old_content.as_deref())?;
+
+        // Blank lines at the end of the file don't matter
+        if old_content.as_deref().map(str::trim_end) == Some(content.trim_end()) {
+            println!("Up to date: {}", path.display());
+            continue;
+        }
+
+        if check {
+            eprintln!("{}", format!("Differs: {}", path.display()).yellow());
+            if let Some(old_content) = &old_content {
+                show_first_difference(old_content, &content);
+            } else {
+                eprintln!("  File does not exist");
+            }
+            drifted.push(path.display().to_string());
+        } else {
+            std::fs::write(&path, content)
+                .with_context(|| format!("Writing changelog file: {}", path.display()))?;
+            println!(
+                "{}",
+                format!("Changelog written: {}", path.display()).green()
+            );
+        }
+    }
+
+    if !drifted.is_empty() {
+        bail!(
+            "Changelog files differ from the store: {}. Run `{} regenerate` to rebuild them.",
+            drifted.join(", "),
+            ctx.binary_name
+        );
+    }
+
+    Ok(())
+}
+
+/// Print the first line that differs between the file on disk and the regenerated content
+fn show_first_difference(old_content: &str, new_content: &str) {
+    let mut old_lines = old_content.lines();
+    let mut new_lines = new_content.lines();
+    let mut line_num = 1;
+    loop {
+        match (old_lines.next(), new_lines.next()) {
+            (Some(old), Some(new)) if old == new => {
+                line_num += 1;
+            }
+            (None, None) => {
+                // Only line endings differ
+                eprintln!("  Line endings differ");
+                return;
+            }
+            (old, new) => {
+                eprintln!("  Line {line_num}:");
+                eprintln!(
+                    "{}",
+                    format!("  - {}", old.unwrap_or("(end of file)")).red()
+                );
+                eprintln!(
+                    "{}",
+                    format!("  + {}", new.unwrap_or("(end of file)")).green()
+                );
+                return;
+            }
+        }
+    }
+}
@@ -1,10 +1,21 @@
+//! Config file structure, defaults and loading
+
+use crate::store::ucfirst;
+use anyhow::{Context, bail};
 use indexmap::IndexMap;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use smart_default::SmartDefault;
+use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
 
 /// e.g. default, stable, eap
 pub type ChannelName = String;
 
+/// e.g. core, cli
+pub type PackageName = String;
+
 /// e.g. 1.2.3
 pub type VersionName = String;
 
@@ -14,6 +25,9 @@ pub type EntryName = String;
 /// Config file with nice comments
 pub const CONFIG_FILE_TEMPLATE: &str = include_str!("assets/config_file_template.toml");
 
+/// Title of the config JSON Schema
+const CONFIG_FILE_SCHEMA_TITLE: &str = "clpack.toml";
+
 /// ENV / dotenv key for the youtrack integration server URL
 /// This is only for unit tests
 pub const ENV_YOUTRACK_URL: &str = "CLPACK_YOUTRACK_URL";
@@ -21,6 +35,39 @@ pub const ENV_YOUTRACK_URL: &str = "CLPACK_YOUTRACK_URL";
 /// ENV / dotenv key for the youtrack integration API token
 pub const ENV_YOUTRACK_TOKEN: &str = "CLPACK_YOUTRACK_TOKEN";
 
+/// ENV / dotenv key for the GitLab integration server URL
+pub const ENV_GITLAB_URL: &str = "CLPACK_GITLAB_URL";
+
+/// ENV / dotenv key for the GitLab integration API token
+pub const ENV_GITLAB_TOKEN: &str = "CLPACK_GITLAB_TOKEN";
+
+/// ENV key with the GitLab project ID, set by GitLab CI
+pub const ENV_GITLAB_CI_PROJECT_ID: &str = "CI_PROJECT_ID";
+
+/// ENV / dotenv key for the GitHub integration API URL
+pub const ENV_GITHUB_API_URL: &str = "CLPACK_GITHUB_API_URL";
+
+/// ENV / dotenv key for the GitHub integration API token
+pub const ENV_GITHUB_TOKEN: &str = "CLPACK_GITHUB_TOKEN";
+
+/// ENV key with the GitHub repository (`owner/repo`), set by GitHub Actions
+pub const ENV_GITHUB_ACTIONS_REPOSITORY: &str = "GITHUB_REPOSITORY";
+
+/// ENV key for the version to pack (same as `cl pack --version`)
+pub const ENV_VERSION: &str = "CLPACK_VERSION";
+
+/// ENV key for the package to work on (same as `--package`)
+pub const ENV_PACKAGE: &str = "CLPACK_PACKAGE";
+
+/// ENV key for the path of the user config file, instead of `~/.config/clpack/config.toml`
+pub const ENV_USER_CONFIG: &str = "CLPACK_USER_CONFIG";
+
+/// Prefix of ENV keys overriding config values, e.g. `CLPACK_GIT__COMMIT=true` for `git.commit`
+const ENV_OVERRIDE_PREFIX: &str = "CLPACK_";
+
+/// Separator of nested keys in ENV overrides
+const ENV_OVERRIDE_NESTING: &str = "__";
+
 #[cfg(test)]
 #[test]
 fn test_template_file() {
@@ -30,8 +77,113 @@ fn test_template_file() {
     assert_eq!(parsed, def);
 }
 
+#[cfg(test)]
+#[test]
+fn test_template_schema() {
+    // Every key in the template must be in the schema, with a matching type, and every key in the schema
+    // must be in the template - commented out if it has no default
+    fn resolve<'a>(
+        root: &'a serde_json::Value,
+        schema: &'a serde_json::Value,
+    ) -> &'a serde_json::Value {
+        match schema["$ref"].as_str() {
+            Some(path) => &root["$defs"][path.trim_start_matches("#/$defs/")],
+            None => schema,
+        }
+    }
+
+    fn type_of(value: &toml::Value) -> &'static str {
+        match value {
+            toml::Value::String(_) => "string",
+            toml::Value::Integer(_) => "integer",
+            toml::Value::Float(_) => "number",
+            toml::Value::Boolean(_) => "boolean",
+            toml::Value::Datetime(_) => "string",
+            toml::Value::Array(_) => "array",
+            toml::Value::Table(_) => "object",
+        }
+    }
+
+    fn check(
+        root: &serde_json::Value,
+        schema: &serde_json::Value,
+        value: &toml::Value,
+        path: &str,
+    ) {
+        fn types_of<'a>(
+            root: &'a serde_json::Value,
+            schema: &'a serde_json::Value,
+        ) -> Vec<&'a str> {
+            let schema = resolve(root, schema);
+            match &schema["type"] {
+                serde_json::Value::String(t) => vec![t.as_str()],
+                serde_json::Value::Array(ts) => ts.iter().filter_map(|t| t.as_str()).collect(),
+                // enum with described variants, untagged enum
+                _ => schema["oneOf"]
+                    .as_array()
+                    .or(schema["anyOf"].as_array())
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|variant| types_of(root, variant))
+                    .collect(),
+            }
+        }
+
+        let types = types_of(root, schema);
+        let schema = resolve(root, schema);
+        let value_type = type_of(value);
+        assert!(
+            types.contains(&value_type) || (value_type == "integer" && types.contains(&"number")),
+            "{path}: {value_type} in the template, {types:?} in the schema"
+        );
+
+        match value {
+            toml::Value::Table(table) => {
+                if let Some(properties) = schema["properties"].as_object() {
+                    for (key, value) in table {
+                        let Some(property) = properties.get(key) else {
+                            panic!("{path}.{key} is not in the schema");
+                        };
+                        check(root, property, value, &format!("{path}.{key}"));
+                    }
+                    for key in properties.keys() {
+                        let commented = regex::Regex::new(&format!(
+                            r"(?m)^# ?(?:{key} = |\[\[?(?:[\w.]+\.)?{key}(?:\.[\w-]+)?\]\]?$)"
+                        ))
+                        .unwrap();
+                        assert!(
+                            table.contains_key(key) || commented.is_match(CONFIG_FILE_TEMPLATE),
+                            "{path}.{key} is missing in the template"
+                        );
+                    }
+                } else {
+                    // map
+                    for (key, value) in table {
+                        check(
+                            root,
+                            &schema["additionalProperties"],
+                            value,
+                            &format!("{path}.{key}"),
+                        );
+                    }
+                }
+            }
+            toml::Value::Array(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    check(root, &schema["items"], item, &format!("{path}[{i}]"));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let schema = Config::json_schema();
+    let template: toml::Value = toml::from_str(CONFIG_FILE_TEMPLATE).unwrap();
+    check(&schema, &schema, &template, "config");
+}
+
 /// Main app configuration file
-#[derive(Debug, Serialize, Deserialize, SmartDefault, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
 #[serde(deny_unknown_fields, default)]
 pub struct Config {
     /// Name / path of the folder managed by clpack
@@ -68,6 +220,7 @@ pub struct Config {
     ///
     /// Users may also specify custom section names when writing the changelog file.
     #[default(vec![
+        "Breaking changes".to_string(),
         "Fixes".to_string(),
         "Improvements".to_string(),
         "New features".to_string(),
@@ -79,6 +232,7 @@ pub struct Config {
     ///
     /// - Key - changelog ID; this can be used in the channel file name. Examples: default, eap, beta
     /// - Value - git branch name to recognize the channel. This is a regex pattern.
+    ///   Alternatively, a table with the `branch` and overrides of other settings for the channel (see `ChannelSettings`)
     ///
     /// At least one channel must be defined, with the name defined in `default_channel`
     ///
@@ -88,9 +242,29 @@ pub struct Config {
     ///
     /// If you have a naming schema like e.g. `beta/1.0` where only the prefix stays the same, you may use e.g. `^beta/.*`
     #[default(IndexMap::from([
-        ("default".to_string(), "/^(?:main|master)$/".to_string())
+        ("default".to_string(), ChannelConfig::Branch("/^(?:main|master)$/".to_string()))
     ]))]
-    pub channels: IndexMap<ChannelName, String>,
+    pub channels: IndexMap<ChannelName, ChannelConfig>,
+
+    /// Packages of a monorepo, each with its own changelog files and releases. Empty for a single project.
+    ///
+    /// Entries are shared - an entry belongs to a package if it is in its subfolder of the entries dir
+    /// (e.g. `entries/core/foo.md`), or lists it in `packages` in the front-matter. Entries that do neither
+    /// belong to all packages.
+    pub packages: IndexMap<PackageName, PackageConfig>,
+
+    /// Environment variables to read the branch name from, in order. Used when git has no branch checked out
+    /// (detached HEAD in CI), or before git if `branch_env_vars_first` is set.
+    #[default(vec![
+        "CI_MERGE_REQUEST_SOURCE_BRANCH_NAME".to_string(),
+        "CI_COMMIT_REF_NAME".to_string(),
+        "GITHUB_HEAD_REF".to_string(),
+        "GITHUB_REF_NAME".to_string(),
+    ])]
+    pub branch_env_vars: Vec<String>,
+
+    /// Read `branch_env_vars` before asking git
+    pub branch_env_vars_first: bool,
 
     /// Regex pattern to extract issue number from a branch name.
     /// There should be one capture group that is the number.
@@ -107,24 +281,286 @@ pub struct Config {
     /// Example: `/^rel\/(\d+.\d+)$/`
     ///
     /// If None, no branch identification will be attempted.
-    ///
-    /// TODO attempt to parse version from package.json, composer.json, Cargo.toml and others
     #[default(Some(r"/^rel\/([\d.]+)$/".to_string()))]
     pub branch_version_pattern: Option<String>,
 
+    /// Files to read the current project version from, used as the suggested version when packing
+    /// and for `cl pack --version auto`. The first source that has a version wins, missing files are skipped.
+    #[default(vec![
+        VersionSource::preset("Cargo.toml", VersionFormat::Cargo),
+        VersionSource::preset("package.json", VersionFormat::Npm),
+        VersionSource::preset("composer.json", VersionFormat::Composer),
+        VersionSource::preset("pyproject.toml", VersionFormat::Pyproject),
+    ])]
+    pub version_sources: Vec<VersionSource>,
+
+    /// Files to write the new version into after packing a release (opt-in, empty by default).
+    /// Same format as `version_sources`; the formatting of the files is kept.
+    pub version_targets: Vec<VersionSource>,
+
+    /// How the suggested version is bumped from the last release, based on the unreleased entries
+    pub bump: BumpConfig,
+
+    /// Git commit and tag after packing a release
+    pub git: GitConfig,
+
+    /// Settings for `cl check`
+    pub check: CheckConfig,
+
+    /// Settings for `cl from-commits`
+    pub conventional_commits: ConventionalCommitsConfig,
+
     /// Integrations config
     pub integrations: IntegrationsConfig,
+
+    /// Editor command for writing entries, e.g. `code --wait`. If None, $VISUAL or $EDITOR is used.
+    /// This is a personal setting, best kept in the user config.
+    pub editor: Option<String>,
+}
+
+/// Channel in the config - a branch pattern, or a table with settings
+#[derive(Debug, Serialize, JsonSchema, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum ChannelConfig {
+    /// Git branch name or /regex/
+    Branch(String),
+    /// Branch and overrides of the global settings
+    Table(ChannelSettings),
+}
+
+impl ChannelConfig {
+    /// Git branch name or /regex/ to recognize the channel. Empty for channels only chosen manually.
+    pub fn branch(&self) -> &str {
+        match self {
+            ChannelConfig::Branch(branch) => branch,
+            ChannelConfig::Table(settings) => &settings.branch,
+        }
+    }
+
+    /// Channel-specific settings, if given as a table
+    pub fn settings(&self) -> Option<&ChannelSettings> {
+        match self {
+            ChannelConfig::Branch(_) => None,
+            ChannelConfig::Table(settings) => Some(settings),
+        }
+    }
+}
+
+// Not derived (untagged), so that errors in the table (e.g. unknown fields) are reported as they are
+impl<'de> Deserialize<'de> for ChannelConfig {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ChannelVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for ChannelVisitor {
+            type Value = ChannelConfig;
+
+            fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+                f.write_str("a branch name or pattern, or a table of channel settings")
+            }
+
+            fn visit_str<E: serde::de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(ChannelConfig::Branch(v.to_string()))
+            }
+
+            fn visit_map<A: serde::de::MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> Result<Self::Value, A::Error> {
+                ChannelSettings::deserialize(serde::de::value::MapAccessDeserializer::new(map))
+                    .map(ChannelConfig::Table)
+            }
+        }
+
+        deserializer.deserialize_any(ChannelVisitor)
+    }
+}
+
+/// Channel given as a table - settings that override the global ones for this channel
+#[derive(Debug, Serialize, Deserialize, JsonSchema, Default, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct ChannelSettings {
+    /// Git branch name or /regex/ to recognize the channel. Empty for channels only chosen manually.
+    pub branch: String,
+
+    /// Changelog file of the channel, instead of `changelog_file_default` / `changelog_file_channel`.
+    /// Supports placeholder `{channel}`, `{Channel}`, `{CHANNEL}`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog_file: Option<String>,
+
+    /// Title of the changelog file, instead of `changelog_header`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changelog_header: Option<String>,
+
+    /// Pattern for release header, instead of `release_header`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release_header: Option<String>,
+
+    /// Regex pattern to extract the release number from a branch name, instead of `branch_version_pattern`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_pattern: Option<String>,
+
+    /// Changelog sections, instead of `sections`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sections: Option<Vec<String>>,
+}
+
+/// Package of a monorepo
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct PackageConfig {
+    /// Folder of the package, relative to the project root
+    pub path: String,
+
+    /// Changelog file of the default channel, relative to the package folder.
+    /// Files of other channels follow `changelog_file_channel`, also relative to the package folder.
+    #[default = "CHANGELOG.md"]
+    pub changelog_file: String,
+
+    /// Channels of the package, same format as the top-level `channels`. Empty to use the top-level channels.
+    pub channels: IndexMap<ChannelName, ChannelConfig>,
+}
+
+/// A file to read the project version from, or to write it into
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct VersionSource {
+    /// Path relative to the project root
+    pub file: String,
+
+    /// File format
+    pub format: VersionFormat,
+
+    /// Dot-separated path to the version for `toml` and `json` (overrides the preset path),
+    /// or a pattern with one capture group for `regex`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl VersionSource {
+    /// Source using the default key of a preset format
+    fn preset(file: &str, format: VersionFormat) -> Self {
+        Self {
+            file: file.to_string(),
+            format,
+            key: None,
+        }
+    }
+}
+
+/// Format of a version source file
+#[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionFormat {
+    /// Cargo.toml - `package.version` or `workspace.package.version`
+    Cargo,
+    /// package.json - `version`
+    Npm,
+    /// composer.json - `version`
+    Composer,
+    /// pyproject.toml - `project.version` or `tool.poetry.version`
+    Pyproject,
+    /// Any TOML file, needs a key
+    Toml,
+    /// Any JSON file, needs a key
+    Json,
+    /// Any text file, the key is a regex with one capture group
+    Regex,
+}
+
+/// Settings for the version bump suggestion (semver)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct BumpConfig {
+    /// Sections that make a major bump. Entries with `breaking = true` in the front-matter do too.
+    #[default(vec!["Breaking changes".to_string()])]
+    pub major_sections: Vec<String>,
+
+    /// Sections that make a minor bump. All other sections make a patch bump.
+    #[default(vec!["New features".to_string()])]
+    pub minor_sections: Vec<String>,
+
+    /// Pre-release identifier per channel, e.g. `beta = "beta"` to suggest versions like 1.2.0-beta.1
+    pub prerelease: IndexMap<ChannelName, String>,
+}
+
+/// Git actions after packing a release
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitConfig {
+    /// Commit the changelog file, the channel file, the released entries and version targets
+    pub commit: bool,
+
+    /// Create an annotated tag, with the release notes as the message
+    pub tag: bool,
+
+    /// Commit message. Supports placeholders `{VERSION}`, `{channel}`, `{Channel}`, `{CHANNEL}`,
+    /// and `{package}` in a monorepo
+    #[default = "Release {VERSION}"]
+    pub commit_message: String,
+
+    /// Tag name. Supports the same placeholders as `commit_message`
+    #[default = "v{VERSION}"]
+    pub tag_name: String,
+
+    /// Channels filter - releases on other channels are not committed or tagged. Empty for all channels.
+    pub channels: Vec<ChannelName>,
+
+    /// Push the tag to `remote` after tagging, with the commit it points to. The branch is not pushed.
+    /// Needed when the GitLab or GitHub integration creates a release for the tag.
+    pub push: bool,
+
+    /// Git remote to push the tag to
+    #[default = "origin"]
+    pub remote: String,
+}
+
+/// Settings for `cl check` (CI gate requiring a changelog entry)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct CheckConfig {
+    /// Merge request label that skips the check. Empty to disable.
+    #[default = "no-changelog"]
+    pub skip_label: String,
+
+    /// Commit message trailer line that skips the check, e.g. `Changelog: skip`. Empty to disable.
+    #[default = "Changelog: skip"]
+    pub skip_trailer: String,
+}
+
+/// Settings for `cl from-commits` (entries generated from Conventional Commits)
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct ConventionalCommitsConfig {
+    /// Commit type to changelog section. Commits of other types are skipped, unless they are breaking.
+    #[default(IndexMap::from([
+        ("feat".to_string(), "New features".to_string()),
+        ("fix".to_string(), "Fixes".to_string()),
+        ("perf".to_string(), "Improvements".to_string()),
+        ("refactor".to_string(), "Internal".to_string()),
+    ]))]
+    pub types: IndexMap<String, String>,
+
+    /// Section for breaking changes (`feat!:` or a `BREAKING CHANGE:` footer)
+    #[default = "Breaking changes"]
+    pub breaking_section: String,
 }
 
 /// Integrations config
-#[derive(Debug, Serialize, Deserialize, SmartDefault, PartialEq, Clone)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
 #[serde(deny_unknown_fields, default)]
 pub struct IntegrationsConfig {
     /// YouTrack integration
     pub youtrack: YouTrackIntegrationConfig,
+
+    /// GitLab integration
+    pub gitlab: GitLabIntegrationConfig,
+
+    /// GitHub integration
+    pub github: GitHubIntegrationConfig,
 }
 
-#[derive(Debug, Serialize, Deserialize, SmartDefault, PartialEq, Clone)]
+/// Settings of the YouTrack integration
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
 #[serde(deny_unknown_fields, default)]
 pub struct YouTrackIntegrationConfig {
     /// Enable the integration
@@ -134,7 +570,7 @@ pub struct YouTrackIntegrationConfig {
     #[default = "https://example.youtrack.cloud"]
     pub url: String,
 
-    /// Channels filter
+    /// Channels filter - releases on other channels do not update YouTrack. Empty to never update it.
     #[default(vec![
         "default".to_string(),
     ])]
@@ -148,4 +584,432 @@ pub struct YouTrackIntegrationConfig {
 
     /// Version prefix
     pub version_prefix: String,
+
+    /// API token. The env variable `CLPACK_YOUTRACK_TOKEN` takes precedence.
+    /// This is a personal setting - keep it in the user config, not in the project.
+    pub token: Option<String>,
+}
+
+/// Settings of the GitLab integration
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitLabIntegrationConfig {
+    /// Enable the integration
+    pub enabled: bool,
+
+    /// URL of the GitLab server (just https://domain)
+    #[default = "https://gitlab.com"]
+    pub url: String,
+
+    /// Project ID or path (e.g. `group/project`). If empty, `CI_PROJECT_ID` from GitLab CI is used.
+    pub project: String,
+
+    /// Channels filter - releases on other channels do not update GitLab. Empty for all channels.
+    #[default(vec![
+        "default".to_string(),
+    ])]
+    pub channels: Vec<ChannelName>,
+
+    /// Create a GitLab release for the tag (`git.tag_name`), with the release notes
+    #[default = true]
+    pub create_release: bool,
+
+    /// Milestone to set on the released issues (e.g. `{VERSION}`). It is created if it does not exist.
+    pub milestone: Option<String>,
+
+    /// Labels to add to the released issues (placeholders like in `git.tag_name` can be used)
+    pub labels: Vec<String>,
+
+    /// Comment to add to the released issues (e.g. `Released in {VERSION}`)
+    pub comment: Option<String>,
+
+    /// Close the released issues
+    pub close_issues: bool,
+
+    /// API token. The env variable `CLPACK_GITLAB_TOKEN` takes precedence.
+    /// This is a personal setting - keep it in the user config, not in the project.
+    pub token: Option<String>,
+}
+
+/// Settings of the GitHub integration
+#[derive(Debug, Serialize, Deserialize, JsonSchema, SmartDefault, PartialEq, Clone)]
+#[serde(deny_unknown_fields, default)]
+pub struct GitHubIntegrationConfig {
+    /// Enable the integration
+    pub enabled: bool,
+
+    /// URL of the GitHub API. For GitHub Enterprise Server, it is `https://domain/api/v3`.
+    #[default = "https://api.github.com"]
+    pub api_url: String,
+
+    /// Repository (`owner/repo`). If empty, `GITHUB_REPOSITORY` from GitHub Actions is used.
+    pub repository: String,
+
+    /// Channels filter - releases on other channels do not update GitHub. Empty for all channels.
+    #[default(vec![
+        "default".to_string(),
+    ])]
+    pub channels: Vec<ChannelName>,
+
+    /// Create (or update) a GitHub release for the tag (`git.tag_name`), with the release notes
+    #[default = true]
+    pub create_release: bool,
+
+    /// Comment to add to the released issues and pull requests (e.g. `Released in v{VERSION}`)
+    pub comment: Option<String>,
+
+    /// API token. The env variable `CLPACK_GITHUB_TOKEN` takes precedence.
+    /// This is a personal setting - keep it in the user config, not in the project.
+    pub token: Option<String>,
+}
+
+/// Where a config value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    /// Built-in default
+    Default,
+    /// Config file (project or user)
+    File(PathBuf),
+    /// Environment variable
+    Env(String),
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigSource::Default => f.write_str("default"),
+            ConfigSource::File(path) => write!(f, "{}", path.display()),
+            ConfigSource::Env(var) => write!(f, "env {var}"),
+        }
+    }
+}
+
+/// Config merged from the project file, the user file and ENV overrides
+#[derive(Debug, Clone)]
+pub struct LoadedConfig {
+    /// The merged config
+    pub config: Config,
+
+    /// Source of each value that is not default, by dot-separated key path
+    pub sources: IndexMap<String, ConfigSource>,
+
+    /// Config files that were found and loaded, in order
+    pub files: Vec<PathBuf>,
+}
+
+impl LoadedConfig {
+    /// Find where a value came from. Values inside a table or array that was set as a whole have its source.
+    pub fn source_of(&self, path: &str) -> &ConfigSource {
+        let mut path = path;
+        loop {
+            if let Some(source) = self.sources.get(path) {
+                return source;
+            }
+            match path.rsplit_once('.') {
+                Some((parent, _)) => path = parent,
+                None => return &ConfigSource::Default,
+            }
+        }
+    }
+}
+
+/// Path of the per-user config file
+pub fn user_config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var(ENV_USER_CONFIG) {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("clpack").join("config.toml"))
+}
+
+impl Config {
+    /// Config with the overrides of a channel applied (see `ChannelSettings`)
+    pub fn for_channel(&self, channel: &str) -> Cow<'_, Config> {
+        let Some(settings) = self.channels.get(channel).and_then(|ch| ch.settings()) else {
+            return Cow::Borrowed(self);
+        };
+
+        let mut config = self.clone();
+        if let Some(changelog_header) = &settings.changelog_header {
+            config.changelog_header = changelog_header.clone();
+        }
+        if let Some(release_header) = &settings.release_header {
+            config.release_header = release_header.clone();
+        }
+        if let Some(version_pattern) = &settings.version_pattern {
+            config.branch_version_pattern = Some(version_pattern.clone());
+        }
+        if let Some(sections) = &settings.sections {
+            config.sections = sections.clone();
+        }
+        Cow::Owned(config)
+    }
+
+    /// Config of a package - its channels, and changelog and version files relative to its folder
+    pub fn for_package(&self, package: &str) -> anyhow::Result<Config> {
+        let Some(package_config) = self.packages.get(package) else {
+            bail!("No such package: {package}");
+        };
+        let in_package = |file: &str| {
+            Path::new(&package_config.path)
+                .join(file)
+                .to_string_lossy()
+                .into_owned()
+        };
+
+        let mut config = self.clone();
+        if !package_config.channels.is_empty() {
+            config.channels = package_config.channels.clone();
+        }
+        for channel in config.channels.values_mut() {
+            if let ChannelConfig::Table(ChannelSettings {
+                changelog_file: Some(file),
+                ..
+            }) = channel
+            {
+                *file = in_package(file);
+            }
+        }
+        config.changelog_file_default = in_package(&package_config.changelog_file);
+        config.changelog_file_channel = in_package(&self.changelog_file_channel);
+        for source in config
+            .version_sources
+            .iter_mut()
+            .chain(config.version_targets.iter_mut())
+        {
+            source.file = in_package(&source.file);
+        }
+        Ok(config)
+    }
+
+    /// Changelog file of a channel, relative to the project root
+    pub fn changelog_file_name(&self, channel: &str) -> String {
+        let template = match self.channels.get(channel).and_then(|ch| ch.settings()) {
+            Some(ChannelSettings {
+                changelog_file: Some(file),
+                ..
+            }) => file,
+            _ if channel == self.default_channel => return self.changelog_file_default.clone(),
+            _ => &self.changelog_file_channel,
+        };
+        template
+            .replace("{channel}", &channel.to_lowercase())
+            .replace("{CHANNEL}", &channel.to_uppercase())
+            .replace("{Channel}", &ucfirst(channel))
+    }
+
+    /// JSON Schema of the config file, for editor autocompletion and validation
+    pub fn json_schema() -> serde_json::Value {
+        let mut schema = schemars::schema_for!(Config);
+        schema.insert("title".into(), CONFIG_FILE_SCHEMA_TITLE.into());
+        schema.to_value()
+    }
+
+    /// Load the config in layers, each overriding the previous one:
+    ///
+    /// 1. Defaults
+    /// 2. Project config file (if it exists)
+    /// 3. User config file (if it exists)
+    /// 4. ENV variables `CLPACK_<KEY>`, with `__` between nested keys (e.g. `CLPACK_INTEGRATIONS__YOUTRACK__TOKEN`)
+    pub fn load(project_file: &Path, user_file: Option<&Path>) -> anyhow::Result<LoadedConfig> {
+        let mut merged = toml::Table::new();
+        let mut sources = IndexMap::new();
+        let mut files = vec![];
+
+        for path in [Some(project_file), user_file].into_iter().flatten() {
+            let Ok(content) = std::fs::read_to_string(path) else {
+                continue;
+            };
+            // Parse on its own first, for errors pointing to the right file and line
+            if let Err(e) = toml::from_str::<Config>(&content) {
+                bail!("Failed to parse config file ({}): {}", path.display(), e);
+            }
+            let table: toml::Table = toml::from_str(&content)?;
+            merge_layer(
+                &mut merged,
+                table,
+                "",
+                &ConfigSource::File(path.to_path_buf()),
+                &mut sources,
+            );
+            files.push(path.to_path_buf());
+        }
+
+        let defaults = serde_json::to_value(Config::default())?;
+        for (var, value) in std::env::vars() {
+            let Some((path, value)) = parse_env_override(&defaults, &var, &value)? else {
+                continue;
+            };
+            // Build the nested table for this one value
+            let mut layer = value;
+            for key in path.iter().rev() {
+                layer = toml::Value::Table(toml::Table::from_iter([(key.clone(), layer)]));
+            }
+            if let toml::Value::Table(layer) = layer {
+                merge_layer(
+                    &mut merged,
+                    layer,
+                    "",
+                    &ConfigSource::Env(var),
+                    &mut sources,
+                );
+            }
+        }
+
+        let config = toml::Value::Table(merged)
+            .try_into()
+            .context("Invalid config after applying the user config and CLPACK_* variables")?;
+
+        Ok(LoadedConfig {
+            config,
+            sources,
+            files,
+        })
+    }
+}
+
+/// Merge a config layer into the merged table, recording the source of the values it sets.
+/// Tables are merged key by key, other values (including arrays) are replaced.
+fn merge_layer(
+    merged: &mut toml::Table,
+    layer: toml::Table,
+    prefix: &str,
+    source: &ConfigSource,
+    sources: &mut IndexMap<String, ConfigSource>,
+) {
+    for (key, value) in layer {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{prefix}.{key}")
+        };
+        match (merged.get_mut(&key), value) {
+            (Some(toml::Value::Table(merged_sub)), toml::Value::Table(layer_sub)) => {
+                merge_layer(merged_sub, layer_sub, &path, source, sources);
+            }
+            (None, toml::Value::Table(layer_sub)) => {
+                // New table - record the values in it one by one
+                let mut merged_sub = toml::Table::new();
+                merge_layer(&mut merged_sub, layer_sub, &path, source, sources);
+                merged.insert(key, toml::Value::Table(merged_sub));
+            }
+            (_, value) => {
+                // Whatever was under this key before is replaced
+                sources.retain(|p, _| !p.starts_with(&format!("{path}.")));
+                sources.insert(path, source.clone());
+                merged.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Parse an ENV variable as a config override. None if it is not one.
+///
+/// Only variables naming a top-level config key are used (others, like `CLPACK_VERSION`, have other meanings).
+/// The value is taken as a string if the default value is a string (or unset), otherwise it is parsed as TOML.
+fn parse_env_override(
+    defaults: &serde_json::Value,
+    var: &str,
+    value: &str,
+) -> anyhow::Result<Option<(Vec<String>, toml::Value)>> {
+    let Some(name) = var.strip_prefix(ENV_OVERRIDE_PREFIX) else {
+        return Ok(None);
+    };
+    let path: Vec<String> = name
+        .split(ENV_OVERRIDE_NESTING)
+        .map(|key| key.to_lowercase())
+        .collect();
+    if path.iter().any(|key| key.is_empty()) || defaults.get(&path[0]).is_none() {
+        return Ok(None);
+    }
+
+    let default = path
+        .iter()
+        .try_fold(defaults, |value, key| value.get(key.as_str()));
+    let value = match default {
+        None | Some(serde_json::Value::Null) | Some(serde_json::Value::String(_)) => {
+            toml::Value::String(value.to_string())
+        }
+        Some(_) => {
+            let parsed: toml::Table = toml::from_str(&format!("value = {value}"))
+                .with_context(|| format!("Invalid TOML value in {var}: {value}"))?;
+            parsed["value"].clone()
+        }
+    };
+    Ok(Some((path, value)))
+}
+
+#[cfg(test)]
+#[test]
+fn test_channel_table() {
+    let config: Config = toml::from_str(
+        r#"
+[channels]
+default = "main"
+
+[channels.beta]
+branch = '/^beta\//'
+release_header = "{VERSION} (beta)"
+sections = ["Fixes"]
+
+[channels.eap]
+changelog_file = "eap/CHANGELOG.md"
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.channels["default"].branch(), "main");
+    assert_eq!(config.channels["beta"].branch(), "/^beta\\//");
+    assert_eq!(config.channels["eap"].branch(), "");
+
+    let beta = config.for_channel("beta");
+    assert_eq!(beta.release_header, "{VERSION} (beta)");
+    assert_eq!(beta.sections, vec!["Fixes"]);
+    assert_eq!(beta.changelog_header, config.changelog_header);
+    assert!(matches!(config.for_channel("default"), Cow::Borrowed(_)));
+
+    assert_eq!(config.changelog_file_name("default"), "CHANGELOG.md");
+    assert_eq!(config.changelog_file_name("beta"), "CHANGELOG-BETA.md");
+    assert_eq!(config.changelog_file_name("eap"), "eap/CHANGELOG.md");
+
+    // Errors in the table are reported as such, not as a mismatch of the untagged enum
+    let err = toml::from_str::<Config>("[channels.beta]\nbrnach = 'beta'\n").unwrap_err();
+    assert!(err.to_string().contains("unknown field `brnach`"), "{err}");
+}
+
+#[cfg(test)]
+#[test]
+fn test_env_override() {
+    let defaults = serde_json::to_value(Config::default()).unwrap();
+
+    assert_eq!(
+        parse_env_override(&defaults, "CLPACK_GIT__COMMIT", "true").unwrap(),
+        Some((
+            vec!["git".to_string(), "commit".to_string()],
+            toml::Value::Boolean(true)
+        ))
+    );
+    assert_eq!(
+        parse_env_override(&defaults, "CLPACK_DATE_FORMAT", "%d.%m.%Y").unwrap(),
+        Some((
+            vec!["date_format".to_string()],
+            toml::Value::String("%d.%m.%Y".to_string())
+        ))
+    );
+    // Unset option - a string
+    assert_eq!(
+        parse_env_override(&defaults, "CLPACK_EDITOR", "vim").unwrap(),
+        Some((
+            vec!["editor".to_string()],
+            toml::Value::String("vim".to_string())
+        ))
+    );
+    assert_eq!(
+        parse_env_override(&defaults, "CLPACK_VERSION", "1.0").unwrap(),
+        None
+    );
+    assert!(parse_env_override(&defaults, "CLPACK_SECTIONS", "[oops").is_err());
 }
@@ -0,0 +1,211 @@
+//! Changelog entry file parsing
+
+use crate::config::{ChannelName, PackageName};
+use anyhow::{Context, bail};
+use faccess::PathExt;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Line delimiting the optional TOML front-matter block at the start of an entry file
+const FRONT_MATTER_DELIMITER: &str = "+++";
+
+/// Metadata of a changelog entry, from the optional front-matter block
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct EntryMeta {
+    /// Issue IDs, in addition to the one recognized in the file name
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<String>,
+
+    /// Authors of the change
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+
+    /// Channels the entry should be released on. Empty for all channels.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<ChannelName>,
+
+    /// Packages of a monorepo the entry belongs to, besides the one whose subfolder it is in.
+    /// Empty for all packages (if not in a subfolder).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<PackageName>,
+
+    /// Internal change - left out of the rendered changelog, but still part of the release
+    /// (e.g. for issue tracker integrations)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub internal: bool,
+
+    /// Breaking change - the suggested release version is a major bump
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub breaking: bool,
+
+    /// Free-form tags
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+/// Parsed changelog entry
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Entry {
+    /// Metadata from the front-matter block (default if there is none)
+    pub meta: EntryMeta,
+
+    /// Content lines grouped by section name, in the order of appearance.
+    /// Lines before the first section heading are stored under an empty name.
+    pub sections: IndexMap<String, String>,
+}
+
+impl Entry {
+    /// Load and parse an entry file
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        if !path.exists() || !path.readable() {
+            bail!(
+                "Changelog entry file missing or not readable: {}",
+                path.display()
+            );
+        }
+
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Reading entry file: {}", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Parsing entry file: {}", path.display()))
+    }
+
+    /// Parse entry text.
+    ///
+    /// - An optional TOML front-matter block, enclosed in `+++` lines, may be at the start
+    /// - Empty lines are discarded
+    /// - Lines starting with `#` are section names
+    /// - Other lines are kept as they are (except trailing whitespace) and stay together
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let (meta, text) = split_front_matter(text)?;
+
+        let mut sections = IndexMap::<String, String>::new();
+
+        let mut current_section = String::new();
+        for line in text.lines() {
+            let line = line.trim_end();
+            let line_trimmed = line.trim();
+            if line_trimmed.is_empty() {
+                continue;
+            }
+            if line_trimmed.starts_with('#') {
+                // It is a section name
+                current_section = line.trim_start_matches(['#', ' ']).to_string();
+            } else if let Some(buffer) = sections.get_mut(&current_section) {
+                buffer.push('\n');
+                buffer.push_str(line);
+            } else {
+                sections.insert(current_section.clone(), line.to_string());
+            }
+        }
+
+        Ok(Self { meta, sections })
+    }
+
+    /// Check if the entry has any content to put in the changelog
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+    }
+
+    /// Check if the entry belongs to a package. `folder` is the package subfolder the entry is in, if any.
+    pub fn is_for_package(&self, folder: Option<&str>, package: &str) -> bool {
+        folder == Some(package)
+            || self.meta.packages.iter().any(|p| p == package)
+            || (folder.is_none() && self.meta.packages.is_empty())
+    }
+
+    /// Check if the entry should be released on a channel
+    pub fn is_for_channel(&self, channel: &str) -> bool {
+        self.meta.channels.is_empty() || self.meta.channels.iter().any(|ch| ch == channel)
+    }
+}
+
+/// Separate the front-matter block from the entry text and parse it
+fn split_front_matter(text: &str) -> anyhow::Result<(EntryMeta, &str)> {
+    let text = text.trim_start_matches('\u{feff}');
+
+    let mut lines = text.split_inclusive('\n');
+    let Some(first) = lines.next() else {
+        return Ok((EntryMeta::default(), text));
+    };
+    if first.trim_end() != FRONT_MATTER_DELIMITER {
+        return Ok((EntryMeta::default(), text));
+    }
+
+    let mut offset = first.len();
+    for line in lines {
+        if line.trim_end() == FRONT_MATTER_DELIMITER {
+            let meta =
+                toml::from_str(&text[first.len()..offset]).context("Invalid front-matter")?;
+            return Ok((meta, &text[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    bail!("Front-matter block is not closed with {FRONT_MATTER_DELIMITER}");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let entry = Entry::parse(
+            "loose line\n\n# Fixes\n- fix one\n  continued   \n\n## New features\n- feature\n# Fixes\n- fix two\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            entry.sections,
+            IndexMap::from([
+                ("".to_string(), "loose line".to_string()),
+                (
+                    "Fixes".to_string(),
+                    "- fix one\n  continued\n- fix two".to_string()
+                ),
+                ("New features".to_string(), "- feature".to_string()),
+            ])
+        );
+
+        assert_eq!(entry.meta, EntryMeta::default());
+
+        assert!(Entry::parse("\n# Fixes\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let entry = Entry::parse(
+            "+++\nissues = [\"SW-1\", \"SW-2\"]\nchannels = [\"beta\"]\ninternal = true\n+++\n# Fixes\n- fix\n",
+        )
+        .unwrap();
+
+        assert_eq!(entry.meta.issues, vec!["SW-1", "SW-2"]);
+        assert!(entry.meta.internal);
+        assert!(entry.is_for_channel("beta"));
+        assert!(!entry.is_for_channel("default"));
+        assert_eq!(
+            entry.sections,
+            IndexMap::from([("Fixes".to_string(), "- fix".to_string())])
+        );
+
+        // Not at the start - just a line of text
+        let entry = Entry::parse("# Fixes\n+++\n").unwrap();
+        assert_eq!(entry.sections["Fixes"], "+++");
+
+        // Packages - by the subfolder or the front-matter
+        let entry = Entry::parse("+++\npackages = [\"app\"]\n+++\n- fix\n").unwrap();
+        assert!(entry.is_for_package(None, "app"));
+        assert!(entry.is_for_package(Some("core"), "core"));
+        assert!(entry.is_for_package(Some("core"), "app"));
+        assert!(!entry.is_for_package(None, "core"));
+        let entry = Entry::parse("- fix\n").unwrap();
+        assert!(entry.is_for_package(None, "core"));
+        assert!(!entry.is_for_package(Some("app"), "core"));
+
+        assert!(Entry::parse("+++\nissues = []\n").is_err());
+        assert!(Entry::parse("+++\nbogus = 1\n+++\n").is_err());
+    }
+}
@@ -1,10 +1,15 @@
+//! Git repository access - branch detection, commits and tags
+
 use crate::AppContext;
 use crate::config::Config;
+use crate::store::ucfirst;
 use crate::utils::empty_to_none::EmptyToNone;
-use anyhow::bail;
+use anyhow::{Context, bail};
 use std::fmt::Display;
 use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
 
+/// Name of a git branch
 #[derive(Debug, Clone)]
 pub struct BranchName(pub String);
 
@@ -14,26 +19,314 @@ impl Display for BranchName {
     }
 }
 
-/// Best effort identify git branch by looking into the .git folder
-pub fn get_branch_name(ctx: &AppContext) -> Option<BranchName> {
-    let path_to_head = ctx.root.join(".git").join("HEAD");
+/// Git repository, found by walking up from a directory
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitRepo {
+    /// Root of the work tree
+    pub work_tree: PathBuf,
+    /// Git dir of the work tree. For linked worktrees and submodules, this is where the `.git` file points to.
+    pub git_dir: PathBuf,
+    /// Git dir with the shared refs - differs from `git_dir` in linked worktrees
+    pub common_dir: PathBuf,
+}
 
-    if !path_to_head.is_file() {
-        return None;
-    }
+/// State of HEAD
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Head {
+    /// Checked out branch, None if detached
+    pub branch: Option<String>,
+    /// Commit SHA, None on a branch without commits
+    pub commit: Option<String>,
+}
 
-    let contents = std::fs::read_to_string(path_to_head).ok()?;
+/// Limit for following symbolic refs, in case they form a loop
+const MAX_SYMREF_DEPTH: usize = 10;
 
-    if let Some(branch) = contents.strip_prefix("ref: refs/heads/") {
-        let b = branch.trim();
-        if b.is_empty() {
-            None
+impl GitRepo {
+    /// Find the repository containing a directory, like git does - walk up until a `.git` dir or file is found.
+    pub fn discover(start: &Path) -> Option<Self> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if dot_git.is_file() {
+                // Linked worktree or submodule - `gitdir: <path>`, relative to the .git file
+                let content = std::fs::read_to_string(&dot_git).ok()?;
+                let path = content.trim().strip_prefix("gitdir:")?.trim();
+                dir.join(path)
+            } else {
+                continue;
+            };
+
+            if !git_dir.join("HEAD").exists() {
+                return None;
+            }
+
+            let common_dir = match std::fs::read_to_string(git_dir.join("commondir")) {
+                Ok(path) => git_dir.join(path.trim()),
+                Err(_) => git_dir.clone(),
+            };
+
+            return Some(Self {
+                work_tree: dir.to_path_buf(),
+                git_dir,
+                common_dir,
+            });
+        }
+        None
+    }
+
+    /// Read a ref file. Refs other than `refs/...` (HEAD) belong to the worktree, the rest is shared.
+    ///
+    /// Returns the content - a SHA, or `ref: <name>` for a symbolic ref.
+    fn read_loose_ref(&self, name: &str) -> Option<String> {
+        let dir = if name.starts_with("refs/") {
+            &self.common_dir
         } else {
-            Some(BranchName(branch.trim().to_owned()))
+            &self.git_dir
+        };
+        let path = dir.join(name);
+
+        // Very old git made HEAD a symlink instead of a symbolic ref
+        if let Ok(target) = std::fs::read_link(&path) {
+            let target = target.strip_prefix(dir).unwrap_or(&target);
+            if target.starts_with("refs") {
+                return Some(format!("ref: {}", target.display()));
+            }
+        }
+
+        std::fs::read_to_string(path)
+            .ok()
+            .map(|content| content.trim().to_string())
+    }
+
+    /// Find a ref in the packed-refs file
+    fn read_packed_ref(&self, name: &str) -> Option<String> {
+        let packed = std::fs::read_to_string(self.common_dir.join("packed-refs")).ok()?;
+        packed
+            .lines()
+            .filter(|line| !line.starts_with('#') && !line.starts_with('^'))
+            .find_map(|line| {
+                let (sha, ref_name) = line.split_once(' ')?;
+                (ref_name.trim() == name).then(|| sha.to_string())
+            })
+    }
+
+    /// Resolve HEAD, following symbolic refs to the branch and its commit
+    pub fn head(&self) -> Head {
+        let mut head = Head::default();
+        let mut name = "HEAD".to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let Some(value) = self
+                .read_loose_ref(&name)
+                .or_else(|| self.read_packed_ref(&name))
+            else {
+                // Branch without commits yet
+                break;
+            };
+
+            let Some(target) = value.strip_prefix("ref:") else {
+                head.commit = Some(value).filter(|sha| !sha.is_empty());
+                break;
+            };
+
+            name = target.trim().to_string();
+            if let Some(branch) = name.strip_prefix("refs/heads/") {
+                head.branch = Some(branch.to_string());
+            }
         }
+        head
+    }
+}
+
+/// Identify the git branch - given on the command line, from the repository containing the project root,
+/// or from CI environment variables (HEAD is usually detached in CI)
+pub fn get_branch_name(ctx: &AppContext) -> Option<BranchName> {
+    find_branch_name(ctx, |var| std::env::var(var).ok())
+}
+
+/// Identify the git branch like `get_branch_name()`, reading environment variables with the given function
+fn find_branch_name(
+    ctx: &AppContext,
+    env_var: impl Fn(&str) -> Option<String>,
+) -> Option<BranchName> {
+    if let Some(branch) = &ctx.branch {
+        return Some(BranchName(branch.clone()));
+    }
+
+    let from_git = || GitRepo::discover(&ctx.root)?.head().branch;
+    let from_env = || {
+        ctx.config.branch_env_vars.iter().find_map(|var| {
+            let value = env_var(var)?;
+            let value = value.trim();
+            Some(
+                value
+                    .strip_prefix("refs/heads/")
+                    .unwrap_or(value)
+                    .to_string(),
+            )
+            .filter(|b| !b.is_empty())
+        })
+    };
+
+    let branch = if ctx.config.branch_env_vars_first {
+        from_env().or_else(from_git)
     } else {
-        None
+        from_git().or_else(from_env)
+    };
+    Some(BranchName(branch?)).filter(|b| !b.0.is_empty())
+}
+
+/// Run a git command in the project root and return its standard output.
+///
+/// Fails if git is not installed or the command exits with an error.
+pub fn run_git(ctx: &AppContext, args: &[&str]) -> anyhow::Result<String> {
+    let output = std::process::Command::new("git")
+        .current_dir(&ctx.root)
+        .args(args)
+        .output()
+        .context("Failed to run git - is it installed?")?;
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
     }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Files changed on a branch, relative to the project root - in commits not reachable from any other
+/// local or remote branch, and uncommitted changes (including untracked files)
+pub fn changed_files(ctx: &AppContext, branch: &BranchName) -> anyhow::Result<Vec<String>> {
+    let committed = run_git(
+        ctx,
+        &[
+            "log",
+            "--format=",
+            "--name-only",
+            "--relative",
+            "HEAD",
+            "--not",
+            &format!("--exclude={branch}"),
+            "--branches",
+            &format!("--exclude=*/{branch}"),
+            "--remotes",
+            "--",
+        ],
+    )?;
+    let uncommitted = run_git(ctx, &["diff", "--name-only", "--relative", "HEAD", "--"])?;
+    let untracked = run_git(ctx, &["ls-files", "--others", "--exclude-standard"])?;
+
+    let mut files = Vec::<String>::new();
+    for file in [committed, uncommitted, untracked]
+        .iter()
+        .flat_map(|out| out.lines())
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+    {
+        if !files.iter().any(|f| f == file) {
+            files.push(file.to_string());
+        }
+    }
+    Ok(files)
+}
+
+/// Get the SHA of the current commit, if in a git repository
+pub fn get_head_commit(ctx: &AppContext) -> Option<String> {
+    match run_git(ctx, &["rev-parse", "--verify", "HEAD"]) {
+        Ok(sha) => Some(sha.trim().to_string()).filter(|s| !s.is_empty()),
+        // git not installed - try reading the repository directly
+        Err(_) => GitRepo::discover(&ctx.root)?.head().commit,
+    }
+}
+
+/// Get the name of the user, for recording who made a release.
+///
+/// Uses git `user.name` if available, or the login name.
+pub fn get_user_name(ctx: &AppContext) -> Option<String> {
+    run_git(ctx, &["config", "user.name"])
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .or_else(|| std::env::var("USER").ok())
+        .or_else(|| std::env::var("USERNAME").ok())
+}
+
+/// Check if a tag exists
+pub fn tag_exists(ctx: &AppContext, name: &str) -> anyhow::Result<bool> {
+    let tags = run_git(ctx, &["tag", "--list", name])?;
+    Ok(!tags.trim().is_empty())
+}
+
+/// Check that a release can be committed and tagged - git runs, there is a commit to build on,
+/// no merge is in progress, the committer identity is set and the remote to push to exists.
+/// Done before the release is written, so that a failing git command does not leave it half-done.
+pub fn check_git_usable(ctx: &AppContext, push_remote: Option<&str>) -> anyhow::Result<()> {
+    run_git(ctx, &["rev-parse", "--verify", "HEAD"]).context(
+        "Git repository with at least one commit is needed to commit and tag the release",
+    )?;
+    if run_git(ctx, &["rev-parse", "--quiet", "--verify", "MERGE_HEAD"]).is_ok() {
+        bail!("A git merge is in progress, finish it before releasing.");
+    }
+    run_git(ctx, &["var", "GIT_COMMITTER_IDENT"])
+        .context("Git committer identity is not set (user.name and user.email)")?;
+    if let Some(remote) = push_remote {
+        run_git(ctx, &["remote", "get-url", remote])
+            .with_context(|| format!("Git remote {remote} to push the tag to is not configured"))?;
+    }
+    Ok(())
+}
+
+/// Stage the given files and commit only them, leaving other staged changes alone
+pub fn commit_files(ctx: &AppContext, files: &[&str], message: &str) -> anyhow::Result<()> {
+    let mut args = vec!["add", "--"];
+    args.extend_from_slice(files);
+    run_git(ctx, &args)?;
+
+    let mut args = vec!["commit", "-m", message, "--"];
+    args.extend_from_slice(files);
+    run_git(ctx, &args)?;
+    Ok(())
+}
+
+/// Create an annotated tag on HEAD. The message is used as it is (lines starting with # are kept).
+pub fn create_annotated_tag(ctx: &AppContext, name: &str, message: &str) -> anyhow::Result<()> {
+    run_git(
+        ctx,
+        &[
+            "tag",
+            "--annotate",
+            "--cleanup=verbatim",
+            "-m",
+            message,
+            name,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Push a tag to a remote, with the commit it points to
+pub fn push_tag(ctx: &AppContext, remote: &str, name: &str) -> anyhow::Result<()> {
+    run_git(ctx, &["push", remote, &format!("refs/tags/{name}")])?;
+    Ok(())
+}
+
+/// Fill placeholders in the commit message, tag name, or another release template (e.g. of an integration)
+pub fn fill_git_template(
+    template: &str,
+    version: &str,
+    channel: &str,
+    package: Option<&str>,
+) -> String {
+    template
+        .replace("{VERSION}", version)
+        .replace("{package}", package.unwrap_or_default())
+        .replace("{channel}", &channel.to_lowercase())
+        .replace("{CHANNEL}", &channel.to_uppercase())
+        .replace("{Channel}", &ucfirst(channel))
 }
 
 impl BranchName {
@@ -99,7 +392,8 @@ impl BranchName {
 
     /// Try to detect a release channel from this branch name (e.g. stable, EAP)
     pub fn parse_channel(&self, config: &Config) -> anyhow::Result<Option<String>> {
-        for (channel_id, template) in &config.channels {
+        for (channel_id, channel) in &config.channels {
+            let template = channel.branch();
             if template.is_empty() {
                 // Channel only for manual choosing
                 continue;
@@ -117,7 +411,7 @@ impl BranchName {
                 }
             } else {
                 // No regex - match it verbatim
-                if &self.0 == template {
+                if self.0 == template {
                     return Ok(Some(channel_id.to_owned()));
                 } else {
                     continue;
@@ -129,11 +423,13 @@ impl BranchName {
 }
 
 /// If the string is encased in slashes, return the inner part. Otherwise, return None.
-fn as_regex_pattern(input: &str) -> Option<&str> {
+pub(crate) fn as_regex_pattern(input: &str) -> Option<&str> {
     input.strip_prefix('/')?.strip_suffix('/')
 }
 
+/// Helper for an optional branch name
 pub trait BranchOpt {
+    /// The branch name, or an empty string
     fn as_str_or_default(&self) -> &str;
 }
 
@@ -146,7 +442,6 @@ impl BranchOpt for Option<BranchName> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::path::PathBuf;
 
     #[test]
     fn test_as_regex_pattern() {
@@ -162,6 +457,8 @@ mod test {
             binary_name: "cl".to_string(),
             config: Default::default(),
             root: PathBuf::from("/tmp/"), // will not be used
+            branch: None,
+            package: None,
         };
 
         assert_eq!(
@@ -185,6 +482,8 @@ mod test {
             binary_name: "cl".to_string(),
             config: Default::default(),
             root: PathBuf::from("/tmp/"), // will not be used
+            branch: None,
+            package: None,
         };
 
         assert_eq!(
@@ -215,6 +514,8 @@ mod test {
             binary_name: "cl".to_string(),
             config: Default::default(),
             root: PathBuf::from("/tmp/"), // will not be used
+            branch: None,
+            package: None,
         };
 
         assert_eq!(
@@ -238,4 +539,154 @@ mod test {
             None
         );
     }
+
+    #[test]
+    fn test_branch_from_ci_env() {
+        let tmp = tempfile::tempdir().unwrap();
+        let detached = tmp.path().join("detached");
+        let on_branch = tmp.path().join("on-branch");
+        for (dir, head) in [
+            (&detached, "0123456789abcdef\n"),
+            (&on_branch, "ref: refs/heads/main\n"),
+        ] {
+            std::fs::create_dir_all(dir.join(".git")).unwrap();
+            std::fs::write(dir.join(".git/HEAD"), head).unwrap();
+        }
+
+        // Repository, env variables, env first, expected branch
+        type EnvVars<'a> = &'a [(&'a str, &'a str)];
+        let cases: &[(&Path, EnvVars, bool, Option<&str>)] = &[
+            // GitLab merge request pipeline - the source branch wins over the ref being built
+            (
+                &detached,
+                &[
+                    ("CI_MERGE_REQUEST_SOURCE_BRANCH_NAME", "12-fix"),
+                    ("CI_COMMIT_REF_NAME", "refs/merge-requests/5/head"),
+                ],
+                false,
+                Some("12-fix"),
+            ),
+            // GitLab branch pipeline
+            (
+                &detached,
+                &[("CI_COMMIT_REF_NAME", "beta/1.0")],
+                false,
+                Some("beta/1.0"),
+            ),
+            // GitHub pull request - the head branch wins over the merge ref name
+            (
+                &detached,
+                &[
+                    ("GITHUB_HEAD_REF", "12-fix"),
+                    ("GITHUB_REF_NAME", "5/merge"),
+                ],
+                false,
+                Some("12-fix"),
+            ),
+            // GitHub push - the head ref is set, but empty
+            (
+                &detached,
+                &[("GITHUB_HEAD_REF", ""), ("GITHUB_REF_NAME", "main")],
+                false,
+                Some("main"),
+            ),
+            (
+                &detached,
+                &[("CI_COMMIT_REF_NAME", "refs/heads/main")],
+                false,
+                Some("main"),
+            ),
+            (&detached, &[], false, None),
+            // A checked out branch wins, unless the env variables go first
+            (
+                &on_branch,
+                &[("CI_COMMIT_REF_NAME", "beta")],
+                false,
+                Some("main"),
+            ),
+            (
+                &on_branch,
+                &[("CI_COMMIT_REF_NAME", "beta")],
+                true,
+                Some("beta"),
+            ),
+            (&on_branch, &[], true, Some("main")),
+        ];
+
+        for (i, (root, vars, env_first, expected)) in cases.iter().enumerate() {
+            let mut ctx = AppContext::new(*root, Default::default());
+            ctx.config.branch_env_vars_first = *env_first;
+            let branch = find_branch_name(&ctx, |var| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            });
+            assert_eq!(branch.as_ref().map(|b| b.0.as_str()), *expected, "case {i}");
+        }
+    }
+
+    #[test]
+    fn test_discover_repo() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().to_path_buf();
+        let git_dir = dir.join(".git");
+        std::fs::create_dir_all(git_dir.join("refs/heads")).unwrap();
+        std::fs::write(git_dir.join("HEAD"), "ref: refs/heads/42-fix\n").unwrap();
+        std::fs::create_dir_all(dir.join("sub/deeper")).unwrap();
+
+        let repo = GitRepo::discover(&dir.join("sub/deeper")).unwrap();
+        assert_eq!(repo.work_tree, dir);
+        assert_eq!(repo.common_dir, git_dir);
+
+        // Branch without commits
+        assert_eq!(
+            repo.head(),
+            Head {
+                branch: Some("42-fix".to_string()),
+                commit: None
+            }
+        );
+
+        // Packed ref
+        std::fs::write(
+            git_dir.join("packed-refs"),
+            "# pack-refs with: peeled fully-peeled sorted\n1111 refs/heads/42-fix\n2222 refs/tags/v1\n^3333\n",
+        )
+        .unwrap();
+        assert_eq!(repo.head().commit, Some("1111".to_string()));
+
+        // Loose ref wins, symbolic ref is followed
+        std::fs::write(git_dir.join("refs/heads/42-fix"), "ref: refs/heads/main\n").unwrap();
+        std::fs::write(git_dir.join("refs/heads/main"), "4444\n").unwrap();
+        assert_eq!(
+            repo.head(),
+            Head {
+                branch: Some("main".to_string()),
+                commit: Some("4444".to_string())
+            }
+        );
+
+        // Linked worktree
+        let worktree = dir.join("wt");
+        let wt_git_dir = git_dir.join("worktrees/wt");
+        std::fs::create_dir_all(&worktree).unwrap();
+        std::fs::create_dir_all(&wt_git_dir).unwrap();
+        std::fs::write(worktree.join(".git"), "gitdir: ../.git/worktrees/wt\n").unwrap();
+        std::fs::write(wt_git_dir.join("commondir"), "../..\n").unwrap();
+        std::fs::write(wt_git_dir.join("HEAD"), "ref: refs/tags/v1\n").unwrap();
+
+        let repo = GitRepo::discover(&worktree).unwrap();
+        assert_eq!(repo.work_tree, worktree);
+        assert_eq!(
+            repo.head(),
+            Head {
+                branch: None,
+                commit: Some("2222".to_string())
+            }
+        );
+
+        // Detached
+        std::fs::write(wt_git_dir.join("HEAD"), "5555\n").unwrap();
+        assert_eq!(repo.head().commit, Some("5555".to_string()));
+    }
 }
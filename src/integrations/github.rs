@@ -0,0 +1,456 @@
+//! GitHub integration (publish the release notes as a GitHub release, comment on the released issues and pull requests)
+
+use crate::config::{
+    ChannelName, ENV_GITHUB_ACTIONS_REPOSITORY, ENV_GITHUB_API_URL, ENV_GITHUB_TOKEN,
+    GitHubIntegrationConfig,
+};
+use crate::git::fill_git_template;
+use crate::integrations::{Integration, IssueNumber, ReleaseEvent, numbered_issues};
+use indexmap::IndexMap;
+use log::{debug, info};
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Integration publishing GitHub releases and commenting on the released issues
+pub struct GitHubIntegration {
+    /// Settings from the config
+    config: GitHubIntegrationConfig,
+    /// API URL - from the env / dotenv, or from the config
+    api_url: String,
+    /// API token - from the env / dotenv, or from the config (user config, usually)
+    token: String,
+    /// Repository (`owner/repo`) - from the config, or from GitHub Actions
+    repository: String,
+}
+
+impl GitHubIntegration {
+    /// Create the integration if it is fully configured - it needs the API URL, the token, the repository and something to do
+    pub fn from_config(ghconf: &GitHubIntegrationConfig) -> Option<Self> {
+        let api_url = dotenv::var(ENV_GITHUB_API_URL)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| ghconf.api_url.clone());
+        if api_url.is_empty() {
+            debug!("GitHub API URL is not set, integration disabled");
+            return None;
+        }
+        let Some(token) = dotenv::var(ENV_GITHUB_TOKEN)
+            .ok()
+            .or_else(|| ghconf.token.clone())
+            .filter(|token| !token.is_empty())
+        else {
+            debug!("GitHub token is not set, integration disabled");
+            return None;
+        };
+        let repository = if ghconf.repository.is_empty() {
+            std::env::var(ENV_GITHUB_ACTIONS_REPOSITORY).unwrap_or_default()
+        } else {
+            ghconf.repository.clone()
+        };
+        if repository.is_empty() {
+            debug!("GitHub repository is not set, integration disabled");
+            return None;
+        }
+        if !ghconf.create_release && Self::comment(ghconf).is_none() {
+            debug!("Nothing to do in GitHub - no release, no comments");
+            return None;
+        }
+
+        Some(Self {
+            config: ghconf.clone(),
+            api_url,
+            token,
+            repository,
+        })
+    }
+
+    /// Comment template, if set
+    fn comment(ghconf: &GitHubIntegrationConfig) -> Option<&str> {
+        ghconf.comment.as_deref().filter(|c| !c.is_empty())
+    }
+}
+
+impl Integration for GitHubIntegration {
+    fn id(&self) -> &'static str {
+        "github"
+    }
+
+    fn name(&self) -> &'static str {
+        "GitHub"
+    }
+
+    fn channels(&self) -> &[ChannelName] {
+        &self.config.channels
+    }
+
+    fn describe_release(&self, event: &ReleaseEvent) -> anyhow::Result<Vec<String>> {
+        let mut lines = vec![];
+        if self.config.create_release {
+            lines.push(format!(
+                "Create or update release {} in {}",
+                event.tag_name(),
+                self.repository
+            ));
+        }
+
+        if Self::comment(&self.config).is_none() {
+            return Ok(lines);
+        }
+
+        let (numbers, others) = numbered_issues(event);
+        if numbers.is_empty() {
+            lines.push("No issues to comment on".to_string());
+        } else {
+            let issues = numbers
+                .iter()
+                .map(|number| format!("#{number}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("Comment on {issues}"));
+        }
+        if !others.is_empty() {
+            lines.push(format!("Not GitHub issues: {}", others.join(", ")));
+        }
+        Ok(lines)
+    }
+
+    fn on_release(&self, event: &ReleaseEvent) -> anyhow::Result<()> {
+        let client = GitHubClient::new(&self.api_url, &self.token, &self.repository)?;
+
+        if self.config.create_release {
+            let tag_name = event.tag_name();
+            // A tag made by clpack was pushed (git.push), otherwise GitHub creates it at the packed commit
+            let target = if event.tagged {
+                None
+            } else {
+                event.release.commit.as_deref()
+            };
+
+            let release = NewRelease {
+                tag_name: &tag_name,
+                target_commitish: target,
+                name: &event.release.version,
+                body: event.notes,
+            };
+            match client.find_release(&tag_name)? {
+                Some(id) => {
+                    info!("Update release {tag_name} in GitHub");
+                    client.update_release(id, &release)?;
+                }
+                None => {
+                    info!("Create release {tag_name} in GitHub");
+                    client.create_release(&release)?;
+                }
+            }
+        }
+
+        if let Some(comment) = Self::comment(&self.config) {
+            let comment = fill_git_template(
+                comment,
+                &event.release.version,
+                event.channel,
+                event.package,
+            );
+            let (numbers, _) = numbered_issues(event);
+            for number in numbers {
+                info!("Comment on #{number} in GitHub");
+                client.add_comment(number, &comment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Release to create or update
+#[derive(Serialize)]
+pub struct NewRelease<'a> {
+    /// Name of the tag
+    pub tag_name: &'a str,
+    /// Commit to create the tag at, if it does not exist
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_commitish: Option<&'a str>,
+    /// Title of the release
+    pub name: &'a str,
+    /// Release notes (Markdown)
+    pub body: &'a str,
+}
+
+/// GitHub API client (with only the bare minimum of the API implemented to satisfy clpack's needs)
+pub struct GitHubClient {
+    /// HTTPS client with default presets to access the API
+    client: reqwest::blocking::Client,
+    /// Base URL of the repository in the API, e.g. `https://api.github.com/repos/owner/repo`
+    repo_url: String,
+}
+
+impl GitHubClient {
+    /// Create a client for a repository (`owner/repo`)
+    pub fn new(api_url: &str, token: &str, repository: &str) -> anyhow::Result<Self> {
+        let token_bearer = format!("Bearer {token}");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Authorization", HeaderValue::from_str(&token_bearer)?);
+        headers.insert("Content-Type", HeaderValue::from_str("application/json")?);
+        headers.insert(
+            "Accept",
+            HeaderValue::from_str("application/vnd.github+json")?,
+        );
+        headers.insert("X-GitHub-Api-Version", HeaderValue::from_str("2022-11-28")?);
+
+        Ok(GitHubClient {
+            repo_url: format!(
+                "{base}/repos/{repository}",
+                base = api_url.trim_end_matches('/')
+            ),
+            client: reqwest::blocking::Client::builder()
+                .default_headers(headers)
+                // GitHub rejects requests without an user agent
+                .user_agent(concat!("clpack/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+        })
+    }
+
+    /// Error from a response body - GitHub sends `message`
+    fn parse_github_error_response(payload: &str) -> anyhow::Error {
+        #[derive(Deserialize)]
+        struct GitHubError {
+            message: String,
+        }
+
+        if let Ok(e) = serde_json::from_str::<GitHubError>(payload) {
+            anyhow::format_err!("Error from GitHub: {}", e.message)
+        } else {
+            anyhow::format_err!("Error from GitHub (unknown response format): {payload}")
+        }
+    }
+
+    /// Send a request with an optional JSON body, to a path in the repository. Returns the status and the response text.
+    fn send<B: Serialize + ?Sized>(
+        &self,
+        method: Method,
+        api_path: &str,
+        body: Option<&B>,
+    ) -> anyhow::Result<(StatusCode, String)> {
+        let url = format!("{}/{}", self.repo_url, api_path.trim_start_matches('/'));
+
+        debug!("{method} {url}");
+
+        let mut request = self.client.request(method, &url);
+        if let Some(body) = body {
+            request = request.body(serde_json::to_string(body)?.into_bytes());
+        }
+        let response = request.send()?;
+
+        let status = response.status();
+        let response_text = response.text()?;
+
+        debug!("Resp = {}", response_text);
+
+        Ok((status, response_text))
+    }
+
+    /// Send a request, fail on an error status. Deserialize response.
+    fn request<B: Serialize + ?Sized, O: DeserializeOwned>(
+        &self,
+        method: Method,
+        api_path: &str,
+        body: Option<&B>,
+    ) -> anyhow::Result<O> {
+        let (status, response_text) = self.send(method, api_path, body)?;
+        if !status.is_success() {
+            return Err(Self::parse_github_error_response(&response_text));
+        }
+        Ok(serde_json::from_str(&response_text)?)
+    }
+
+    /// Send a GET request, `None` if the resource does not exist. Deserialize response.
+    fn get_optional<O: DeserializeOwned>(&self, api_path: &str) -> anyhow::Result<Option<O>> {
+        let (status, response_text) = self.send::<()>(Method::GET, api_path, None)?;
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(Self::parse_github_error_response(&response_text));
+        }
+        Ok(Some(serde_json::from_str(&response_text)?))
+    }
+
+    /// Find the release of a tag. Returns its ID.
+    pub fn find_release(&self, tag_name: &str) -> anyhow::Result<Option<u64>> {
+        #[derive(Deserialize)]
+        struct Release {
+            id: u64,
+        }
+
+        Ok(self
+            .get_optional::<Release>(&format!("releases/tags/{tag_name}"))?
+            .map(|release| release.id))
+    }
+
+    /// Create a release
+    pub fn create_release(&self, release: &NewRelease) -> anyhow::Result<()> {
+        let _: Value = self.request(Method::POST, "releases", Some(release))?;
+        Ok(())
+    }
+
+    /// Change the name and notes of a release
+    pub fn update_release(&self, id: u64, release: &NewRelease) -> anyhow::Result<()> {
+        let _: Value = self.request(Method::PATCH, &format!("releases/{id}"), Some(release))?;
+        Ok(())
+    }
+
+    /// Add a comment to an issue or a pull request
+    pub fn add_comment(&self, number: IssueNumber, text: &str) -> anyhow::Result<()> {
+        let mut body = IndexMap::new();
+        body.insert("body", text);
+        let _: Value = self.request(
+            Method::POST,
+            &format!("issues/{number}/comments"),
+            Some(&body),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::entry::Entry;
+    use crate::integrations::mock::mock_server;
+    use crate::store::Release;
+
+    /// Integration talking to a mock server
+    fn integration(api_url: String) -> GitHubIntegration {
+        GitHubIntegration {
+            config: GitHubIntegrationConfig {
+                enabled: true,
+                comment: Some("Released in v{VERSION}".to_string()),
+                ..Default::default()
+            },
+            api_url,
+            token: "secret".to_string(),
+            repository: "owner/app".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_github_release() {
+        let (url, server) = mock_server(vec![
+            (404, r#"{"message": "Not Found"}"#),
+            (201, r#"{"id": 1}"#),
+            (201, r#"{"id": 100}"#),
+            (201, r#"{"id": 101}"#),
+        ]);
+        let integration = integration(url);
+
+        let config = Config::default();
+        let release = Release {
+            version: "1.2.0".to_string(),
+            commit: Some("abc123".to_string()),
+            ..Default::default()
+        };
+        let mut entries = IndexMap::new();
+        entries.insert(
+            "12-fix-crash".to_string(),
+            Entry::parse("# Fixes\n- Fixed a crash\n").unwrap(),
+        );
+        entries.insert(
+            "SW-3-other".to_string(),
+            Entry::parse("+++\nissues = [\"#15\"]\n+++\n# Fixes\n- Other\n").unwrap(),
+        );
+        let event = ReleaseEvent {
+            config: &config,
+            channel: &"default".to_string(),
+            package: None,
+            release: &release,
+            entries: &entries,
+            notes: "## [1.2.0] - 2026-10-16\n\n### Fixes\n\n- Fixed a crash\n- Other",
+            tagged: false,
+        };
+
+        assert_eq!(
+            integration.describe_release(&event).unwrap(),
+            vec![
+                "Create or update release v1.2.0 in owner/app",
+                "Comment on #12, #15",
+                "Not GitHub issues: SW-3",
+            ]
+        );
+
+        integration.on_release(&event).unwrap();
+        let requests = server.join().unwrap();
+
+        assert_eq!(
+            requests.iter().map(|r| r.line.as_str()).collect::<Vec<_>>(),
+            vec![
+                "GET /repos/owner/app/releases/tags/v1.2.0",
+                "POST /repos/owner/app/releases",
+                "POST /repos/owner/app/issues/12/comments",
+                "POST /repos/owner/app/issues/15/comments",
+            ]
+        );
+        assert!(
+            requests
+                .iter()
+                .all(|r| r.headers["authorization"] == "Bearer secret")
+        );
+        assert!(requests[0].headers["user-agent"].starts_with("clpack/"));
+        assert_eq!(
+            requests[1].body,
+            serde_json::json!({
+                "tag_name": "v1.2.0",
+                "target_commitish": "abc123",
+                "name": "1.2.0",
+                "body": event.notes,
+            })
+        );
+        assert_eq!(
+            requests[2].body,
+            serde_json::json!({"body": "Released in v1.2.0"})
+        );
+    }
+
+    #[test]
+    fn test_github_update_release() {
+        let (url, server) = mock_server(vec![(200, r#"{"id": 5}"#), (200, r#"{"id": 5}"#)]);
+        let mut integration = integration(url);
+        integration.config.comment = None;
+
+        // The tag is made and pushed by clpack, the release already exists
+        let config = Config::default();
+        let release = Release {
+            version: "2.0.0".to_string(),
+            commit: Some("abc123".to_string()),
+            ..Default::default()
+        };
+        let entries = IndexMap::new();
+        let event = ReleaseEvent {
+            config: &config,
+            channel: &"default".to_string(),
+            package: None,
+            release: &release,
+            entries: &entries,
+            notes: "## [2.0.0]",
+            tagged: true,
+        };
+        integration.on_release(&event).unwrap();
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests.iter().map(|r| r.line.as_str()).collect::<Vec<_>>(),
+            vec![
+                "GET /repos/owner/app/releases/tags/v2.0.0",
+                "PATCH /repos/owner/app/releases/5",
+            ]
+        );
+        assert_eq!(
+            requests[1].body,
+            serde_json::json!({"tag_name": "v2.0.0", "name": "2.0.0", "body": "## [2.0.0]"})
+        );
+    }
+}
@@ -0,0 +1,503 @@
+//! GitLab integration (create a release for the tag, set milestone / labels on the released issues, close them)
+
+use crate::config::{
+    ChannelName, ENV_GITLAB_CI_PROJECT_ID, ENV_GITLAB_TOKEN, ENV_GITLAB_URL,
+    GitLabIntegrationConfig,
+};
+use crate::git::fill_git_template;
+use crate::integrations::{Integration, IssueNumber, ReleaseEvent, numbered_issues};
+use crate::store::ucfirst;
+use indexmap::IndexMap;
+use log::{debug, info};
+use reqwest::Method;
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Integration creating GitLab releases and updating the released issues
+pub struct GitLabIntegration {
+    /// Settings from the config
+    config: GitLabIntegrationConfig,
+    /// Server URL - from the env / dotenv, or from the config
+    url: String,
+    /// API token - from the env / dotenv, or from the config (user config, usually)
+    token: String,
+    /// Project ID or path - from the config, or from GitLab CI
+    project: String,
+}
+
+impl GitLabIntegration {
+    /// Create the integration if it is fully configured - it needs the URL, the token, the project and something to do
+    pub fn from_config(glconf: &GitLabIntegrationConfig) -> Option<Self> {
+        let url = dotenv::var(ENV_GITLAB_URL)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| glconf.url.clone());
+        if url.is_empty() {
+            debug!("GitLab URL is not set, integration disabled");
+            return None;
+        }
+        let Some(token) = dotenv::var(ENV_GITLAB_TOKEN)
+            .ok()
+            .or_else(|| glconf.token.clone())
+            .filter(|token| !token.is_empty())
+        else {
+            debug!("GitLab token is not set, integration disabled");
+            return None;
+        };
+        let project = if glconf.project.is_empty() {
+            std::env::var(ENV_GITLAB_CI_PROJECT_ID).unwrap_or_default()
+        } else {
+            glconf.project.clone()
+        };
+        if project.is_empty() {
+            debug!("GitLab project is not set, integration disabled");
+            return None;
+        }
+        if !glconf.create_release && !Self::updates_issues(glconf) {
+            debug!("Nothing to do in GitLab - no release, no issue changes");
+            return None;
+        }
+
+        Some(Self {
+            config: glconf.clone(),
+            url,
+            token,
+            project,
+        })
+    }
+
+    /// Check if the released issues are changed in any way
+    fn updates_issues(glconf: &GitLabIntegrationConfig) -> bool {
+        Self::milestone(glconf).is_some()
+            || !glconf.labels.is_empty()
+            || Self::comment(glconf).is_some()
+            || glconf.close_issues
+    }
+
+    /// Milestone template, if set
+    fn milestone(glconf: &GitLabIntegrationConfig) -> Option<&str> {
+        glconf.milestone.as_deref().filter(|m| !m.is_empty())
+    }
+
+    /// Comment template, if set
+    fn comment(glconf: &GitLabIntegrationConfig) -> Option<&str> {
+        glconf.comment.as_deref().filter(|c| !c.is_empty())
+    }
+
+    /// Fill placeholders in a template for the release
+    fn fill(template: &str, event: &ReleaseEvent) -> String {
+        fill_git_template(
+            template,
+            &event.release.version,
+            event.channel,
+            event.package,
+        )
+    }
+
+    /// Labels to add, comma separated, with placeholders filled
+    fn labels(&self, event: &ReleaseEvent) -> String {
+        self.config
+            .labels
+            .iter()
+            .map(|label| Self::fill(label, event))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl Integration for GitLabIntegration {
+    fn id(&self) -> &'static str {
+        "gitlab"
+    }
+
+    fn name(&self) -> &'static str {
+        "GitLab"
+    }
+
+    fn channels(&self) -> &[ChannelName] {
+        &self.config.channels
+    }
+
+    fn describe_release(&self, event: &ReleaseEvent) -> anyhow::Result<Vec<String>> {
+        let mut lines = vec![];
+        if self.config.create_release {
+            lines.push(format!(
+                "Create release {} in {}",
+                event.tag_name(),
+                self.project
+            ));
+        }
+
+        if !Self::updates_issues(&self.config) {
+            return Ok(lines);
+        }
+
+        let mut changes = vec![];
+        if let Some(milestone) = Self::milestone(&self.config) {
+            changes.push(format!("set milestone {}", Self::fill(milestone, event)));
+        }
+        if !self.config.labels.is_empty() {
+            changes.push(format!("add labels {}", self.labels(event)));
+        }
+        if Self::comment(&self.config).is_some() {
+            changes.push("comment".to_string());
+        }
+        if self.config.close_issues {
+            changes.push("close".to_string());
+        }
+
+        let (iids, foreign) = numbered_issues(event);
+        if iids.is_empty() {
+            lines.push("No issues to update".to_string());
+        } else {
+            let issues = iids
+                .iter()
+                .map(|iid| format!("#{iid}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            lines.push(format!("{} on {issues}", ucfirst(&changes.join(", "))));
+        }
+        if !foreign.is_empty() {
+            lines.push(format!("Not GitLab issues: {}", foreign.join(", ")));
+        }
+        Ok(lines)
+    }
+
+    fn on_release(&self, event: &ReleaseEvent) -> anyhow::Result<()> {
+        let client = GitLabClient::new(&self.url, &self.token, &self.project)?;
+
+        if self.config.create_release {
+            let tag_name = event.tag_name();
+            info!("Create release {tag_name} in GitLab");
+            // A tag made by clpack was pushed (git.push), otherwise GitLab creates it at the packed commit
+            let git_ref = if event.tagged {
+                None
+            } else {
+                event.release.commit.as_deref()
+            };
+            client.create_release(&tag_name, &event.release.version, event.notes, git_ref)?;
+        }
+
+        if !Self::updates_issues(&self.config) {
+            return Ok(());
+        }
+
+        let (iids, _) = numbered_issues(event);
+        if iids.is_empty() {
+            return Ok(());
+        }
+
+        let milestone_id = match Self::milestone(&self.config) {
+            Some(milestone) => {
+                Some(client.find_or_create_milestone(&Self::fill(milestone, event))?)
+            }
+            None => None,
+        };
+        let comment = Self::comment(&self.config).map(|c| Self::fill(c, event));
+
+        for iid in iids {
+            info!("Update issue #{iid} in GitLab");
+            if milestone_id.is_some() || !self.config.labels.is_empty() || self.config.close_issues
+            {
+                client.update_issue(
+                    iid,
+                    &IssueUpdate {
+                        milestone_id,
+                        add_labels: (!self.config.labels.is_empty()).then(|| self.labels(event)),
+                        state_event: self.config.close_issues.then_some("close"),
+                    },
+                )?;
+            }
+            if let Some(comment) = &comment {
+                client.add_comment(iid, comment)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Changes of an issue (`PUT /projects/:id/issues/:iid`)
+#[derive(Serialize)]
+struct IssueUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    milestone_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    add_labels: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    state_event: Option<&'static str>,
+}
+
+/// GitLab API client (with only the bare minimum of the API implemented to satisfy clpack's needs)
+pub struct GitLabClient {
+    /// HTTPS client with default presets to access the API
+    client: reqwest::blocking::Client,
+    /// Base URL of the project in the API, e.g. `https://gitlab.com/api/v4/projects/group%2Fproject`
+    project_url: String,
+}
+
+impl GitLabClient {
+    /// Create a client for a project, given by its ID or path
+    pub fn new(url: &str, token: &str, project: &str) -> anyhow::Result<Self> {
+        let mut headers = HeaderMap::new();
+        headers.insert("PRIVATE-TOKEN", HeaderValue::from_str(token)?);
+        headers.insert("Content-Type", HeaderValue::from_str("application/json")?);
+        headers.insert("Accept", HeaderValue::from_str("application/json")?);
+
+        Ok(GitLabClient {
+            project_url: format!(
+                "{base}/api/v4/projects/{project}",
+                base = url.trim_end_matches('/'),
+                project = project.replace('/', "%2F")
+            ),
+            client: reqwest::blocking::Client::builder()
+                .default_headers(headers)
+                .build()?,
+        })
+    }
+
+    /// Error from a response body - GitLab sends `message` or `error`
+    fn parse_gitlab_error_response(payload: &str) -> anyhow::Error {
+        let message = serde_json::from_str::<Value>(payload)
+            .ok()
+            .and_then(|v| v.get("message").or_else(|| v.get("error")).cloned());
+        match message {
+            Some(Value::String(message)) => anyhow::format_err!("Error from GitLab: {message}"),
+            Some(message) => anyhow::format_err!("Error from GitLab: {message}"),
+            None => anyhow::format_err!("Error from GitLab (unknown response format): {payload}"),
+        }
+    }
+
+    /// Send a request with query parameters and an optional JSON body, to a path in the project. Deserialize response.
+    fn request<B: Serialize + ?Sized, O: DeserializeOwned>(
+        &self,
+        method: Method,
+        api_path: &str,
+        query: &[(&str, &str)],
+        body: Option<&B>,
+    ) -> anyhow::Result<O> {
+        let url = format!("{}/{}", self.project_url, api_path.trim_start_matches('/'));
+
+        debug!("{method} {url}");
+
+        let mut request = self.client.request(method, &url).query(query);
+        if let Some(body) = body {
+            request = request.body(serde_json::to_string(body)?.into_bytes());
+        }
+        let response = request.send()?;
+
+        let is_ok = response.status().is_success();
+        let response_text = response.text()?;
+
+        debug!("Resp = {}", response_text);
+
+        if !is_ok {
+            return Err(Self::parse_gitlab_error_response(&response_text));
+        }
+
+        Ok(serde_json::from_str(&response_text)?)
+    }
+
+    /// Create a release for a tag. GitLab creates the tag at `git_ref` if it does not exist yet.
+    pub fn create_release(
+        &self,
+        tag_name: &str,
+        name: &str,
+        description: &str,
+        git_ref: Option<&str>,
+    ) -> anyhow::Result<()> {
+        #[derive(Serialize)]
+        struct NewRelease<'a> {
+            tag_name: &'a str,
+            name: &'a str,
+            description: &'a str,
+            #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+            git_ref: Option<&'a str>,
+        }
+
+        let _: Value = self.request(
+            Method::POST,
+            "releases",
+            &[],
+            Some(&NewRelease {
+                tag_name,
+                name,
+                description,
+                git_ref,
+            }),
+        )?;
+        Ok(())
+    }
+
+    /// Find a milestone of the project by its title, or create it. Returns its ID.
+    pub fn find_or_create_milestone(&self, title: &str) -> anyhow::Result<u64> {
+        #[derive(Deserialize)]
+        struct Milestone {
+            id: u64,
+            title: String,
+        }
+
+        let found: Vec<Milestone> =
+            self.request::<(), _>(Method::GET, "milestones", &[("title", title)], None)?;
+        if let Some(milestone) = found.into_iter().find(|m| m.title == title) {
+            return Ok(milestone.id);
+        }
+
+        info!("Create milestone {title} in GitLab");
+        let mut body = IndexMap::new();
+        body.insert("title", title);
+        let created: Milestone = self.request(Method::POST, "milestones", &[], Some(&body))?;
+        Ok(created.id)
+    }
+
+    /// Change an issue
+    fn update_issue(&self, iid: IssueNumber, update: &IssueUpdate) -> anyhow::Result<()> {
+        let _: Value = self.request(Method::PUT, &format!("issues/{iid}"), &[], Some(update))?;
+        Ok(())
+    }
+
+    /// Add a comment to an issue
+    pub fn add_comment(&self, iid: IssueNumber, text: &str) -> anyhow::Result<()> {
+        let mut body = IndexMap::new();
+        body.insert("body", text);
+        let _: Value = self.request(
+            Method::POST,
+            &format!("issues/{iid}/notes"),
+            &[],
+            Some(&body),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::Config;
+    use crate::entry::Entry;
+    use crate::integrations::mock::mock_server;
+    use crate::store::Release;
+
+    #[test]
+    fn test_gitlab_release() {
+        let (url, server) = mock_server(vec![
+            (201, r#"{"tag_name": "v1.2.0"}"#),
+            (200, r#"[{"id": 3, "title": "1.2.0-rc"}]"#),
+            (201, r#"{"id": 7, "title": "1.2.0"}"#),
+            (200, r#"{"iid": 12}"#),
+            (201, r#"{"id": 100}"#),
+            (200, r#"{"iid": 15}"#),
+            (201, r#"{"id": 101}"#),
+        ]);
+
+        let integration = GitLabIntegration {
+            config: GitLabIntegrationConfig {
+                enabled: true,
+                milestone: Some("{VERSION}".to_string()),
+                labels: vec!["released".to_string(), "{VERSION}".to_string()],
+                comment: Some("Released in {VERSION}".to_string()),
+                close_issues: true,
+                ..Default::default()
+            },
+            url,
+            token: "secret".to_string(),
+            project: "group/app".to_string(),
+        };
+
+        let config = Config::default();
+        let release = Release {
+            version: "1.2.0".to_string(),
+            commit: Some("abc123".to_string()),
+            ..Default::default()
+        };
+        let mut entries = IndexMap::new();
+        entries.insert(
+            "12-fix-crash".to_string(),
+            Entry::parse("# Fixes\n- Fixed a crash\n").unwrap(),
+        );
+        entries.insert(
+            "SW-3-other".to_string(),
+            Entry::parse("+++\nissues = [\"#15\", \"#12\"]\n+++\n# Fixes\n- Other\n").unwrap(),
+        );
+        let event = ReleaseEvent {
+            config: &config,
+            channel: &"default".to_string(),
+            package: None,
+            release: &release,
+            entries: &entries,
+            notes: "## [1.2.0] - 2026-10-16\n\n### Fixes\n\n- Fixed a crash\n- Other",
+            tagged: false,
+        };
+
+        assert_eq!(
+            integration.describe_release(&event).unwrap(),
+            vec![
+                "Create release v1.2.0 in group/app",
+                "Set milestone 1.2.0, add labels released,1.2.0, comment, close on #12, #15",
+                "Not GitLab issues: SW-3",
+            ]
+        );
+
+        integration.on_release(&event).unwrap();
+        let requests = server.join().unwrap();
+
+        assert_eq!(
+            requests.iter().map(|r| r.line.as_str()).collect::<Vec<_>>(),
+            vec![
+                "POST /api/v4/projects/group%2Fapp/releases",
+                "GET /api/v4/projects/group%2Fapp/milestones?title=1.2.0",
+                "POST /api/v4/projects/group%2Fapp/milestones",
+                "PUT /api/v4/projects/group%2Fapp/issues/12",
+                "POST /api/v4/projects/group%2Fapp/issues/12/notes",
+                "PUT /api/v4/projects/group%2Fapp/issues/15",
+                "POST /api/v4/projects/group%2Fapp/issues/15/notes",
+            ]
+        );
+        assert!(
+            requests
+                .iter()
+                .all(|r| r.headers["private-token"] == "secret")
+        );
+        assert_eq!(
+            requests[0].body,
+            serde_json::json!({
+                "tag_name": "v1.2.0",
+                "name": "1.2.0",
+                "description": event.notes,
+                "ref": "abc123",
+            })
+        );
+        assert_eq!(requests[2].body, serde_json::json!({"title": "1.2.0"}));
+        assert_eq!(
+            requests[3].body,
+            serde_json::json!({
+                "milestone_id": 7,
+                "add_labels": "released,1.2.0",
+                "state_event": "close",
+            })
+        );
+        assert_eq!(
+            requests[4].body,
+            serde_json::json!({"body": "Released in 1.2.0"})
+        );
+    }
+
+    #[test]
+    fn test_gitlab_error() {
+        let (url, server) = mock_server(vec![(409, r#"{"message": "Release already exists"}"#)]);
+        let client = GitLabClient::new(&url, "secret", "42").unwrap();
+        let err = client
+            .create_release("v1.0", "1.0", "notes", None)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Error from GitLab: Release already exists");
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].line, "POST /api/v4/projects/42/releases");
+        assert_eq!(
+            requests[0].body,
+            serde_json::json!({"tag_name": "v1.0", "name": "1.0", "description": "notes"})
+        );
+    }
+}
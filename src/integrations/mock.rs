@@ -0,0 +1,71 @@
+//! Mock HTTP server for testing API clients of integrations
+
+use indexmap::IndexMap;
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::JoinHandle;
+
+/// A request received by the mock server
+#[derive(Debug)]
+pub struct Recorded {
+    /// e.g. `GET /api/v4/projects/1/milestones?title=1.0`
+    pub line: String,
+    /// Headers, with lowercase names
+    pub headers: IndexMap<String, String>,
+    /// Request body, parsed
+    pub body: Value,
+}
+
+/// Start a mock API server answering requests in order with the given status codes and bodies.
+/// Returns its URL, and a handle to join for the received requests.
+pub fn mock_server(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<Recorded>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = std::thread::spawn(move || {
+        let mut recorded = vec![];
+        for (status, response) in responses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            // Drop the HTTP version
+            let line = line.rsplit_once(' ').unwrap().0.to_string();
+
+            let mut headers = IndexMap::new();
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                let header = header.trim_end();
+                if header.is_empty() {
+                    break;
+                }
+                let (name, value) = header.split_once(": ").unwrap();
+                headers.insert(name.to_lowercase(), value.to_string());
+            }
+            let content_length = headers
+                .get("content-length")
+                .map_or(0, |len| len.parse().unwrap());
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            recorded.push(Recorded {
+                line,
+                headers,
+                body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+            });
+
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                response.len()
+            )
+            .unwrap();
+        }
+        recorded
+    });
+
+    (url, handle)
+}
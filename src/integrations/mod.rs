@@ -1,2 +1,322 @@
-/// Third party service (e.g. issue trackers) integrations
+//! Third party service (e.g. issue trackers) integrations
+
+use crate::config::{ChannelName, Config, EntryName, IntegrationsConfig};
+use crate::entry::Entry;
+use crate::git::{BranchName, fill_git_template};
+use crate::store::Release;
+use indexmap::IndexMap;
+
+#[cfg(feature = "youtrack")]
 pub mod youtrack;
+
+#[cfg(feature = "gitlab")]
+pub mod gitlab;
+
+#[cfg(feature = "github")]
+pub mod github;
+
+#[cfg(all(test, any(feature = "gitlab", feature = "github")))]
+mod mock;
+
+/// IDs of all integrations, and if they are compiled in (each needs the Cargo feature of the same name)
+pub const KNOWN_INTEGRATIONS: &[(&str, bool)] = &[
+    ("youtrack", cfg!(feature = "youtrack")),
+    ("gitlab", cfg!(feature = "gitlab")),
+    ("github", cfg!(feature = "github")),
+];
+
+/// A changelog entry was created
+pub struct EntryEvent<'a> {
+    /// Config of the project (or package)
+    pub config: &'a Config,
+    /// Name of the entry, without extension
+    pub name: &'a str,
+    /// The parsed entry
+    pub entry: &'a Entry,
+}
+
+/// A release is being made
+pub struct ReleaseEvent<'a> {
+    /// Config of the project (or package)
+    pub config: &'a Config,
+    /// Channel of the release
+    pub channel: &'a ChannelName,
+    /// Package of a monorepo the release is for
+    pub package: Option<&'a str>,
+    /// The release, with its version and metadata
+    pub release: &'a Release,
+    /// Entries of the release, loaded
+    pub entries: &'a IndexMap<EntryName, Entry>,
+    /// Release notes, as written in the changelog file
+    pub notes: &'a str,
+    /// clpack tags the release (`git.tag`) - only in the local repository, the tag is not pushed
+    pub tagged: bool,
+}
+
+impl ReleaseEvent<'_> {
+    /// Name of the release tag (`git.tag_name`, filled)
+    pub fn tag_name(&self) -> String {
+        fill_git_template(
+            &self.config.git.tag_name,
+            &self.release.version,
+            self.channel,
+            self.package,
+        )
+    }
+}
+
+/// Third party service notified about changelog events.
+///
+/// For a release, the hooks are called in order:
+///
+/// 1. `describe_release()` - to show what will be done, also in a dry run
+/// 2. `on_before_release()` - before anything is written, also in a dry run. Only checks, no changes.
+/// 3. `on_release()` - after the release was written
+pub trait Integration {
+    /// ID used in the config and on the command line, e.g. `youtrack`
+    fn id(&self) -> &'static str;
+
+    /// Name shown to the user, e.g. `YouTrack`
+    fn name(&self) -> &'static str;
+
+    /// Channels the integration runs on. Empty for all channels, unless `runs_on()` says otherwise.
+    fn channels(&self) -> &[ChannelName];
+
+    /// Check if the integration runs for releases on a channel
+    fn runs_on(&self, channel: &str) -> bool {
+        self.channels().is_empty() || self.channels().iter().any(|ch| ch == channel)
+    }
+
+    /// Describe what `on_release()` would do, one line per action
+    fn describe_release(&self, _event: &ReleaseEvent) -> anyhow::Result<Vec<String>> {
+        Ok(vec![])
+    }
+
+    /// A changelog entry was created
+    fn on_entry_created(&self, _event: &EntryEvent) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A release is about to be written. An error stops the release.
+    fn on_before_release(&self, _event: &ReleaseEvent) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// A release was written
+    fn on_release(&self, event: &ReleaseEvent) -> anyhow::Result<()>;
+}
+
+/// Issues of a release, from the entry file names and from front-matter, without duplicates, each with
+/// the entry it was found in. Also returns entries without any issue.
+pub fn release_issues(event: &ReleaseEvent) -> (IndexMap<String, EntryName>, Vec<EntryName>) {
+    let mut issues = IndexMap::<String, EntryName>::new();
+    let mut unrecognized = vec![];
+    for (name, entry) in event.entries {
+        match BranchName(name.clone()).parse_issue(event.config) {
+            Ok(Some(issue_num)) => {
+                issues.entry(issue_num).or_insert_with(|| name.clone());
+            }
+            _ if entry.meta.issues.is_empty() => {
+                unrecognized.push(name.clone());
+            }
+            _ => {}
+        }
+        for issue_num in &entry.meta.issues {
+            issues
+                .entry(issue_num.clone())
+                .or_insert_with(|| name.clone());
+        }
+    }
+    (issues, unrecognized)
+}
+
+/// Issue number in a GitLab or GitHub project (the `#123` one)
+pub type IssueNumber = u64;
+
+/// Issues of a release with a numeric ID (`#` is optional), as used by GitLab and GitHub, without duplicates.
+/// Also returns the other issue IDs.
+pub fn numbered_issues(event: &ReleaseEvent) -> (Vec<IssueNumber>, Vec<String>) {
+    let (issues, _) = release_issues(event);
+    let mut numbers = vec![];
+    let mut others = vec![];
+    for issue in issues.into_keys() {
+        match issue.trim_start_matches('#').parse::<IssueNumber>() {
+            Ok(number) => {
+                if !numbers.contains(&number) {
+                    numbers.push(number);
+                }
+            }
+            Err(_) => others.push(issue),
+        }
+    }
+    (numbers, others)
+}
+
+/// Integrations enabled in the config
+#[derive(Default)]
+pub struct Registry {
+    /// Integrations ready to run
+    integrations: Vec<Box<dyn Integration>>,
+    /// Integrations enabled in the config, but not compiled in
+    compiled_out: Vec<&'static str>,
+}
+
+impl Registry {
+    /// Create the integrations enabled (and fully configured) in the config
+    pub fn from_config(config: &IntegrationsConfig) -> Self {
+        let mut registry = Self::default();
+
+        if config.youtrack.enabled {
+            #[cfg(feature = "youtrack")]
+            if let Some(integration) = youtrack::YouTrackIntegration::from_config(&config.youtrack)
+            {
+                registry.register(Box::new(integration));
+            }
+            #[cfg(not(feature = "youtrack"))]
+            registry.compiled_out.push("youtrack");
+        }
+
+        if config.gitlab.enabled {
+            #[cfg(feature = "gitlab")]
+            if let Some(integration) = gitlab::GitLabIntegration::from_config(&config.gitlab) {
+                registry.register(Box::new(integration));
+            }
+            #[cfg(not(feature = "gitlab"))]
+            registry.compiled_out.push("gitlab");
+        }
+
+        if config.github.enabled {
+            #[cfg(feature = "github")]
+            if let Some(integration) = github::GitHubIntegration::from_config(&config.github) {
+                registry.register(Box::new(integration));
+            }
+            #[cfg(not(feature = "github"))]
+            registry.compiled_out.push("github");
+        }
+
+        registry
+    }
+
+    /// Add an integration, e.g. one implemented outside of clpack
+    pub fn register(&mut self, integration: Box<dyn Integration>) {
+        self.integrations.push(integration);
+    }
+
+    /// All integrations
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Integration> {
+        self.integrations.iter().map(|i| i.as_ref())
+    }
+
+    /// Integrations running for releases on a channel
+    pub fn for_channel(&self, channel: &str) -> impl Iterator<Item = &dyn Integration> {
+        self.iter().filter(move |i| i.runs_on(channel))
+    }
+
+    /// Find an integration by its ID
+    pub fn get(&self, id: &str) -> Option<&dyn Integration> {
+        self.iter().find(|i| i.id() == id)
+    }
+
+    /// IDs of integrations enabled in the config, but left out of the build (the Cargo feature of the same name)
+    pub fn compiled_out(&self) -> &[&'static str] {
+        &self.compiled_out
+    }
+
+    /// Run `on_entry_created()` of all integrations
+    pub fn entry_created(&self, event: &EntryEvent) -> anyhow::Result<()> {
+        for integration in self.iter() {
+            integration.on_entry_created(event)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Integration recording the entries it was told about
+    struct Recorder {
+        channels: Vec<ChannelName>,
+        entries: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Integration for Recorder {
+        fn id(&self) -> &'static str {
+            "recorder"
+        }
+
+        fn name(&self) -> &'static str {
+            "Recorder"
+        }
+
+        fn channels(&self) -> &[ChannelName] {
+            &self.channels
+        }
+
+        fn on_entry_created(&self, event: &EntryEvent) -> anyhow::Result<()> {
+            self.entries.borrow_mut().push(event.name.to_string());
+            Ok(())
+        }
+
+        fn on_release(&self, _event: &ReleaseEvent) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        // Nothing is enabled by default
+        let mut registry = Registry::from_config(&IntegrationsConfig::default());
+        assert_eq!(registry.iter().count(), 0);
+        assert!(registry.compiled_out().is_empty());
+
+        let entries = Rc::new(RefCell::new(vec![]));
+        registry.register(Box::new(Recorder {
+            channels: vec!["default".to_string()],
+            entries: entries.clone(),
+        }));
+        assert!(registry.get("recorder").is_some());
+        assert!(registry.get("youtrack").is_none());
+        assert_eq!(registry.for_channel("default").count(), 1);
+        assert_eq!(registry.for_channel("beta").count(), 0);
+
+        // Entry hooks run regardless of channels
+        let config = Config::default();
+        let entry = Entry::parse("# Fixes\n- Fixed it\n").unwrap();
+        registry
+            .entry_created(&EntryEvent {
+                config: &config,
+                name: "SW-1-fix",
+                entry: &entry,
+            })
+            .unwrap();
+        assert_eq!(*entries.borrow(), vec!["SW-1-fix"]);
+
+        // No channel filter
+        let recorder = Recorder {
+            channels: vec![],
+            entries,
+        };
+        assert!(recorder.runs_on("beta"));
+
+        // YouTrack keeps its old meaning of an empty filter - no channels
+        #[cfg(feature = "youtrack")]
+        {
+            let mut config = IntegrationsConfig::default();
+            config.youtrack.enabled = true;
+            config.youtrack.token = Some("secret".to_string());
+            config.youtrack.version_field = Some("Fix version".to_string());
+            let registry = Registry::from_config(&config);
+            assert_eq!(registry.for_channel("default").count(), 1);
+            assert_eq!(registry.for_channel("beta").count(), 0);
+
+            config.youtrack.channels.clear();
+            let registry = Registry::from_config(&config);
+            assert!(registry.get("youtrack").is_some());
+            assert_eq!(registry.for_channel("default").count(), 0);
+        }
+    }
+}
@@ -1,107 +1,218 @@
 //! Youtrack integration (mark issues as Released when packing to changelog, change Available in version)
 
-use crate::config::{ChannelName, ENV_YOUTRACK_TOKEN, ENV_YOUTRACK_URL, VersionName};
-use crate::git::BranchName;
+use crate::config::EntryName;
+use crate::config::{
+    ChannelName, ENV_YOUTRACK_TOKEN, ENV_YOUTRACK_URL, VersionName, YouTrackIntegrationConfig,
+};
+use crate::integrations::{Integration, ReleaseEvent, release_issues};
 use crate::store::Release;
-use anyhow::{Context, bail};
+use anyhow::bail;
 use chrono::{DateTime, Utc};
-use log::debug;
+use indexmap::IndexMap;
+use log::{debug, info, warn};
 use reqwest::header::{HeaderMap, HeaderValue};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::sync::LazyLock;
+
+/// `SW-123` - numeric IDs (`123`, `#123`) are GitLab or GitHub issues, not YouTrack ones
+static YOUTRACK_ISSUE: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"^[A-Za-z][A-Za-z0-9_]*-\d+$").unwrap());
 
 /// ID of a youtrack project
 type ProjectId = String;
 
-pub fn youtrack_integration_enabled(config: &crate::Config, channel: &ChannelName) -> bool {
-    let ytconf = &config.integrations.youtrack;
-    ytconf.enabled
-        // Channel filter
-        && ytconf.channels.contains(&channel)
-        // URL is required
-        && (!ytconf.url.is_empty() || dotenv::var(ENV_YOUTRACK_URL).is_ok_and(|v| !v.is_empty()))
-        // Token is required
-        && dotenv::var(ENV_YOUTRACK_TOKEN).is_ok_and(|v| !v.is_empty())
-        // Check if we have something to do
-        && (ytconf.version_field.as_ref().is_some_and(|v| !v.is_empty())
-            || ytconf
+/// Marks released issues in YouTrack
+pub struct YouTrackIntegration {
+    /// Settings from the config
+    config: YouTrackIntegrationConfig,
+    /// Server URL - from the env / dotenv, or from the config
+    url: String,
+    /// API token - from the env / dotenv, or from the config (user config, usually)
+    token: String,
+}
+
+impl YouTrackIntegration {
+    /// Create the integration if it is fully configured - it needs the URL, the token and something to do
+    pub fn from_config(ytconf: &YouTrackIntegrationConfig) -> Option<Self> {
+        let url = dotenv::var(ENV_YOUTRACK_URL)
+            .ok()
+            .filter(|url| !url.is_empty())
+            .unwrap_or_else(|| ytconf.url.clone());
+        if url.is_empty() {
+            debug!("YouTrack URL is not set, integration disabled");
+            return None;
+        }
+        let Some(token) = youtrack_token(ytconf) else {
+            debug!("YouTrack token is not set, integration disabled");
+            return None;
+        };
+        if ytconf
+            .version_field
+            .as_deref()
+            .unwrap_or_default()
+            .is_empty()
+            && ytconf
                 .released_state
-                .as_ref()
-                .is_some_and(|v| !v.is_empty()))
+                .as_deref()
+                .unwrap_or_default()
+                .is_empty()
+        {
+            debug!("Nothing to do in YouTrack - no version field, no target state");
+            return None;
+        }
+
+        Some(Self {
+            config: ytconf.clone(),
+            url,
+            token,
+        })
+    }
+
+    /// Version name as it is in YouTrack
+    fn prefixed_version(&self, release: &Release) -> String {
+        format!("{}{}", self.config.version_prefix, release.version)
+    }
 }
 
-pub fn youtrack_integration_on_release(
-    config: &crate::Config,
-    release: Release,
-) -> anyhow::Result<()> {
-    let ytconf = &config.integrations.youtrack;
-    let url = dotenv::var(ENV_YOUTRACK_URL)
-        .ok()
-        .unwrap_or_else(|| ytconf.url.clone());
+impl Integration for YouTrackIntegration {
+    fn id(&self) -> &'static str {
+        "youtrack"
+    }
 
-    if url.is_empty() {
-        bail!("YouTrack URL is empty!");
+    fn name(&self) -> &'static str {
+        "YouTrack"
     }
-    let token = dotenv::var(ENV_YOUTRACK_TOKEN).context("Error getting YouTrack token")?;
 
-    if token.is_empty() {
-        bail!("YouTrack token is empty!");
+    fn channels(&self) -> &[ChannelName] {
+        &self.config.channels
     }
 
-    let client = YouTrackClient::new(url, &token)?;
+    /// Unlike other integrations, an empty channel list means no channels, as it always did
+    fn runs_on(&self, channel: &str) -> bool {
+        self.config.channels.iter().any(|ch| ch == channel)
+    }
 
-    let mut project_id_opt = None;
-    let mut set_version_opt = None;
+    fn describe_release(&self, event: &ReleaseEvent) -> anyhow::Result<Vec<String>> {
+        let (issues, others, unrecognized) = youtrack_issues(event);
 
-    let prefixed_version = format!("{}{}", ytconf.version_prefix, release.version);
+        let mut changes = vec![];
+        if let Some(field) = self
+            .config
+            .version_field
+            .as_deref()
+            .filter(|f| !f.is_empty())
+        {
+            changes.push(format!(
+                "\"{field}\" = {}",
+                self.prefixed_version(event.release)
+            ));
+        }
+        if let Some(state) = self
+            .config
+            .released_state
+            .as_deref()
+            .filter(|s| !s.is_empty())
+        {
+            changes.push(format!("State = {state}"));
+        }
 
-    let date = chrono::Utc::now();
-    for entry in release.entries {
-        let branch_name = BranchName(entry);
-        let Ok(Some(issue_num)) = branch_name.parse_issue(config) else {
-            eprintln!("No issue number recognized in {}", branch_name.0);
-            continue;
-        };
+        let mut lines = vec![];
+        if issues.is_empty() {
+            lines.push("No issues to update".to_string());
+        } else {
+            let issues = issues.keys().cloned().collect::<Vec<_>>().join(", ");
+            lines.push(format!("Set {} on {issues}", changes.join(", ")));
+        }
+        if !others.is_empty() {
+            lines.push(format!("Not YouTrack issues: {}", others.join(", ")));
+        }
+        if !unrecognized.is_empty() {
+            lines.push(format!(
+                "No issue number recognized in {}",
+                unrecognized.join(", ")
+            ));
+        }
+        Ok(lines)
+    }
 
-        // Assume all tickets belong to the same project
+    fn on_release(&self, event: &ReleaseEvent) -> anyhow::Result<()> {
+        let client = YouTrackClient::new(&self.url, &self.token)?;
 
-        if project_id_opt.is_none() {
-            match client.find_project_id(&issue_num) {
-                Ok(project_id) => {
-                    project_id_opt = Some(project_id);
-                }
-                Err(e) => {
-                    eprintln!("Failed to find project number from {issue_num}: {e}");
-                    continue;
+        let mut project_id_opt = None;
+        let mut set_version_opt = None;
+
+        let prefixed_version = self.prefixed_version(event.release);
+
+        let (issues, _, _) = youtrack_issues(event);
+
+        let date = chrono::Utc::now();
+        for (issue_num, entry_name) in issues {
+            // Assume all tickets belong to the same project
+
+            if project_id_opt.is_none() {
+                match client.find_project_id(&issue_num) {
+                    Ok(project_id) => {
+                        project_id_opt = Some(project_id);
+                    }
+                    Err(e) => {
+                        warn!("Failed to find project number from {issue_num}: {e}");
+                        continue;
+                    }
                 }
             }
-        }
 
-        let project_id = project_id_opt.as_ref().unwrap(); // We know it is set now
+            let project_id = project_id_opt.as_ref().unwrap(); // We know it is set now
 
-        if let Some(field) = &ytconf.version_field
-            && set_version_opt.is_none()
-        {
-            let set_version = SetVersion {
-                field_name: field,
-                version: &prefixed_version,
-            };
+            if let Some(field) = &self.config.version_field
+                && set_version_opt.is_none()
+            {
+                let set_version = SetVersion {
+                    field_name: field,
+                    version: &prefixed_version,
+                };
 
-            client.ensure_version_exists_in_project(&project_id, &set_version, Some(date))?;
+                client.ensure_version_exists_in_project(project_id, &set_version, Some(date))?;
 
-            set_version_opt = Some(set_version);
+                set_version_opt = Some(set_version);
+            }
+
+            info!("Update issue {issue_num} ({entry_name}) in YouTrack");
+            client.set_issue_version_and_state_by_name(
+                &issue_num,
+                set_version_opt.as_ref(),
+                self.config.released_state.as_deref(),
+            )?;
         }
 
-        println!("Update issue {issue_num} ({}) in YouTrack", branch_name.0);
-        client.set_issue_version_and_state_by_name(
-            &issue_num,
-            set_version_opt.as_ref(),
-            ytconf.released_state.as_deref(),
-        )?;
+        Ok(())
     }
+}
+
+/// Issues of the release with a YouTrack ID, each with the entry it was found in.
+/// Also returns the other issue IDs, and entries without any issue.
+fn youtrack_issues(
+    event: &ReleaseEvent,
+) -> (IndexMap<String, EntryName>, Vec<String>, Vec<EntryName>) {
+    let (mut issues, unrecognized) = release_issues(event);
+    let mut others = vec![];
+    issues.retain(|issue, _| {
+        let ours = YOUTRACK_ISSUE.is_match(issue);
+        if !ours {
+            others.push(issue.clone());
+        }
+        ours
+    });
+    (issues, others, unrecognized)
+}
 
-    Ok(())
+/// Get the API token - from the env / dotenv, or from the config (user config, usually)
+fn youtrack_token(ytconf: &YouTrackIntegrationConfig) -> Option<String> {
+    dotenv::var(ENV_YOUTRACK_TOKEN)
+        .ok()
+        .or_else(|| ytconf.token.clone())
+        .filter(|token| !token.is_empty())
 }
 
 /// YouTrack API client (with only the bare minimum of the API implemented to satisfy clpack's needs)
@@ -143,7 +254,7 @@ impl YouTrackClient {
     }
 
     fn parse_youtrack_error_response(payload: &str) -> anyhow::Error {
-        if let Ok(e) = serde_json::from_str::<YoutrackErrorResponse>(&payload) {
+        if let Ok(e) = serde_json::from_str::<YoutrackErrorResponse>(payload) {
             anyhow::format_err!("Error from YouTrack: {} - {}", e.error, e.error_description)
         } else {
             anyhow::format_err!("Error from YouTrack (unknown response format): {payload}")
@@ -276,7 +387,7 @@ impl YouTrackClient {
         // Find the field we want in the list (XXX this can probably be done with some API query?)
         let mut field_bundle = None;
         for entry in fields {
-            if &entry.field.name == version_info.field_name
+            if entry.field.name == version_info.field_name
                 && let Some(bundle) = entry.bundle
             {
                 field_bundle = Some((entry.field.id, bundle.id));
@@ -292,7 +403,7 @@ impl YouTrackClient {
             );
         };
 
-        println!("Found YouTrack version field, checking defined versions");
+        info!("Found YouTrack version field, checking defined versions");
 
         #[derive(Deserialize)]
         struct YTVersion {
@@ -309,14 +420,14 @@ impl YouTrackClient {
 
         // Is our version defined?
         if versions.iter().any(|v| v.name == version_info.version) {
-            eprintln!(
+            warn!(
                 "Version {v} already exists in YouTrack",
                 v = version_info.version
             );
             return Ok(());
         }
 
-        println!(
+        info!(
             "Creating version in YouTrack: {v}",
             v = version_info.version
         );
@@ -360,7 +471,7 @@ impl YouTrackClient {
         // {"releaseDate":1758619201,"released":true,"archived":false,"name":"TEST2","id":"232-358","$type":"VersionBundleElement"}
 
         debug!("Created version entry = {:#?}", resp);
-        println!("Version {v} created in YouTrack.", v = version_info.version);
+        info!("Version {v} created in YouTrack.", v = version_info.version);
 
         Ok(())
     }
@@ -421,7 +532,7 @@ impl YouTrackClient {
         }
 
         if custom_fields.is_empty() {
-            eprintln!("Nothing to do in YouTrack - no version field, no target state.");
+            warn!("Nothing to do in YouTrack - no version field, no target state.");
             return Ok(());
         }
 
@@ -440,7 +551,7 @@ impl YouTrackClient {
         // Example success:
         // {"customFields":[{"value":null,"name":"Type","$type":"SingleEnumIssueCustomField"},{"value":{"name":"Released","$type":"StateBundleElement"},"name":"State","$type":"StateIssueCustomField"},{"value":null,"name":"Assignee","$type":"SingleUserIssueCustomField"},{"value":null,"name":"Priority","$type":"SingleEnumIssueCustomField"},{"value":{"name":"Internal tooling","$type":"EnumBundleElement"},"name":"Category","$type":"SingleEnumIssueCustomField"},{"value":[],"name":"Customer","$type":"MultiEnumIssueCustomField"},{"value":null,"name":"Customer Funding","$type":"SingleEnumIssueCustomField"},{"value":null,"name":"Product Stream","$type":"SingleEnumIssueCustomField"},{"value":null,"name":"Estimation","$type":"PeriodIssueCustomField"},{"value":{"$type":"PeriodValue"},"name":"Spent time","$type":"PeriodIssueCustomField"},{"value":null,"name":"Due Date","$type":"DateIssueCustomField"},{"value":[],"name":"Affected version","$type":"MultiVersionIssueCustomField"},{"value":{"name":"TEST2","$type":"VersionBundleElement"},"name":"Available in version","$type":"SingleVersionIssueCustomField"},{"value":null,"name":"SlackAlertSent","$type":"SimpleIssueCustomField"},{"value":13.0,"name":"Dev costs","$type":"SimpleIssueCustomField"}],"id":"2-25820","$type":"Issue"}
 
-        println!("YouTrack issue {issue_id} updated.");
+        info!("YouTrack issue {issue_id} updated.");
 
         debug!("Response to request to edit issue: {resp:?}");
         Ok(())
@@ -458,10 +569,44 @@ pub struct SetVersion<'a> {
 
 #[cfg(test)]
 mod tests {
-    use super::{SetVersion, YouTrackClient};
-    use crate::config::{ENV_YOUTRACK_TOKEN, ENV_YOUTRACK_URL};
+    use super::{SetVersion, YouTrackClient, youtrack_issues};
+    use crate::config::{Config, ENV_YOUTRACK_TOKEN, ENV_YOUTRACK_URL};
+    use crate::entry::Entry;
+    use crate::integrations::ReleaseEvent;
+    use crate::store::Release;
+    use indexmap::IndexMap;
     use log::{LevelFilter, debug};
 
+    #[test]
+    fn test_youtrack_issues() {
+        let config = Config::default();
+        let release = Release::default();
+        let mut entries = IndexMap::new();
+        entries.insert(
+            "SW-1-fix".to_string(),
+            Entry::parse("+++\nissues = [\"#12\", \"SW-2\"]\n+++\n- fix\n").unwrap(),
+        );
+        entries.insert(
+            "13-gitlab-style".to_string(),
+            Entry::parse("- fix\n").unwrap(),
+        );
+        entries.insert("no-issue".to_string(), Entry::parse("- fix\n").unwrap());
+        let event = ReleaseEvent {
+            config: &config,
+            channel: &"default".to_string(),
+            package: None,
+            release: &release,
+            entries: &entries,
+            notes: "",
+            tagged: false,
+        };
+
+        let (issues, others, unrecognized) = youtrack_issues(&event);
+        assert_eq!(issues.keys().collect::<Vec<_>>(), vec!["SW-1", "SW-2"]);
+        assert_eq!(others, vec!["#12", "13"]);
+        assert_eq!(unrecognized, vec!["no-issue"]);
+    }
+
     // #[test] // Disabled
     #[allow(unused)]
     fn test_youtrack_communication() {
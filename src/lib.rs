@@ -0,0 +1,125 @@
+//! clpack - changelog keeping across multiple release channels
+//!
+//! This crate is the engine of the `cl` tool. It can be used to drive releases from other tools
+//! or from `build.rs`. It does no terminal I/O - progress is reported with the `log` crate,
+//! and questions are left to the caller.
+//!
+//! The main parts are:
+//!
+//! - [`AppContext`] - the project: its root folder and [`Config`]. [`AppContext::discover`] finds and loads it.
+//! - [`Store`] - the changelog data folder with entries and releases of each channel
+//! - [`Release`] - a release of a channel, rendered into the changelog file
+//! - [`BranchName`] - the git branch, used to detect the channel, version and issue
+//!
+//! ```no_run
+//! use clpack::{AppContext, Store};
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let ctx = AppContext::discover(&std::env::current_dir()?)?;
+//! let mut store = Store::new(&ctx, false)?;
+//!
+//! let channel = ctx.config.default_channel.clone();
+//! let release = store.new_release(&channel, "1.2.0".to_string())?;
+//! if !release.entries.is_empty() {
+//!     println!("{}", store.render_release(&channel, &release)?);
+//!     store.create_release(channel, release)?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::config::PackageName;
+use crate::git::GitRepo;
+use std::path::{Path, PathBuf};
+
+pub mod config;
+
+pub mod git;
+
+pub mod store;
+
+pub mod entry;
+
+pub mod version;
+
+pub mod validate;
+
+pub mod package;
+
+pub mod integrations;
+
+mod utils;
+
+pub use config::Config;
+pub use git::BranchName;
+pub use store::{Release, Store};
+
+/// Default config file name, looked up in the project root
+pub const CONFIG_FILE_NAME: &str = "clpack.toml";
+
+/// The project clpack works on - everything else takes it as a parameter
+#[derive(Debug)]
+pub struct AppContext {
+    /// Name of the cl binary
+    pub binary_name: String,
+
+    /// Config loaded from file or defaults
+    pub config: Config,
+
+    /// Root of the project
+    pub root: PathBuf,
+
+    /// Branch name given on the command line, overrides detection
+    pub branch: Option<String>,
+
+    /// Package of a monorepo to work on. The config is then the package config.
+    pub package: Option<PackageName>,
+}
+
+impl AppContext {
+    /// Create a context for a project with an already loaded config
+    pub fn new(root: impl Into<PathBuf>, config: Config) -> Self {
+        Self {
+            binary_name: "cl".to_string(),
+            config,
+            root: root.into(),
+            branch: None,
+            package: None,
+        }
+    }
+
+    /// Find the project containing a directory and load its config, layered with the user config
+    /// and env variables. Defaults are used if there is no config file.
+    pub fn discover(cwd: &Path) -> anyhow::Result<Self> {
+        let root = find_project_root(cwd);
+        let loaded = Config::load(
+            &root.join(CONFIG_FILE_NAME),
+            config::user_config_path().as_deref(),
+        )?;
+        Ok(Self::new(root, loaded.config))
+    }
+
+    /// Work on a package of a monorepo - switch to its config
+    pub fn select_package(&mut self, package: PackageName) -> anyhow::Result<()> {
+        self.config = self.config.for_package(&package)?;
+        self.package = Some(package);
+        Ok(())
+    }
+}
+
+/// Find the project root - the nearest directory with a config file, up to the root of the git repository.
+/// Without a config file, it is the git repository root, or the current directory outside of git.
+pub fn find_project_root(cwd: &Path) -> PathBuf {
+    let repo_root = GitRepo::discover(cwd).map(|repo| repo.work_tree);
+
+    for dir in cwd.ancestors() {
+        if dir.join(CONFIG_FILE_NAME).is_file() {
+            return dir.to_path_buf();
+        }
+        if repo_root.as_deref() == Some(dir) {
+            break;
+        }
+    }
+
+    repo_root.unwrap_or_else(|| cwd.to_path_buf())
+}
@@ -1,18 +1,28 @@
+use crate::action_check::{ClCheck, cl_check};
+use crate::action_commits::{ClFromCommits, cl_from_commits};
+use crate::action_config::{cl_config_schema, cl_config_show, cl_config_validate, print_report};
+use crate::action_flush::cl_flush;
+use crate::action_import::{ClImport, cl_import};
 use crate::action_init::{ClInit, cl_init};
-use crate::action_log::cl_log;
-use crate::action_pack::cl_pack;
+use crate::action_log::{ClLog, cl_log};
+use crate::action_pack::{ClPack, cl_pack};
+use crate::action_regenerate::cl_regenerate;
 use crate::action_status::cl_status;
-use crate::config::{ChannelName, Config};
 use anyhow::bail;
 use clap::builder::NonEmptyStringValueParser;
+use clpack::config::{
+    ChannelName, Config, ENV_PACKAGE, ENV_VERSION, PackageName, user_config_path,
+};
+use clpack::package::{changed_packages, detect_package};
+use clpack::{AppContext, CONFIG_FILE_NAME, find_project_root};
 use colored::Colorize;
+use indexmap::IndexMap;
+use log::Level;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
 use std::path::PathBuf;
 use std::process::exit;
 
-mod config;
-
-mod git;
-
 mod action_log;
 mod action_pack;
 
@@ -20,33 +30,84 @@ mod action_init;
 
 mod action_status;
 
-mod store;
+mod action_flush;
+
+mod action_check;
+
+mod action_regenerate;
+
+mod action_import;
 
-mod utils;
+mod action_commits;
 
-mod integrations;
+mod action_config;
 
+mod ui;
+
+#[cfg(test)]
+mod test_util;
+
+/// Exit code when there are no unreleased entries (`cl pack --yes`).
+/// Codes are kept above the ones used by clap (2 for invalid arguments).
+pub const EXIT_NOTHING_TO_RELEASE: i32 = 10;
+
+/// Exit code when the version to pack was already released (`cl pack --version`)
+pub const EXIT_VERSION_EXISTS: i32 = 11;
+
+/// Error ending the program with a specific exit code, so scripts can tell the outcomes apart
 #[derive(Debug)]
-pub struct AppContext {
-    /// Name of the cl binary
-    pub binary_name: String,
+pub struct ExitCodeError {
+    /// Process exit code
+    pub code: i32,
+    /// Message shown to the user
+    pub message: String,
+}
 
-    /// Config loaded from file or defaults
-    pub config: Config,
+impl ExitCodeError {
+    pub fn new(code: i32, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
 
-    /// Root of the project
-    pub root: PathBuf,
+impl Display for ExitCodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
 }
 
+impl std::error::Error for ExitCodeError {}
+
 fn main() {
-    env_logger::init();
+    init_logger();
 
     if let Err(e) = main_try() {
+        if let Some(e) = e.downcast_ref::<ExitCodeError>() {
+            eprintln!("{}", e.message.yellow().bold());
+            exit(e.code);
+        }
         eprintln!("{}", format!("{:?}", e).red().bold());
         exit(1);
     }
 }
 
+/// Messages logged by the library are shown as plain output - info and up by default, more with RUST_LOG
+fn init_logger() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("clpack=info"))
+        .format(|buf, record| {
+            let message = record.args().to_string();
+            match record.level() {
+                Level::Error => writeln!(buf, "{}", message.red()),
+                Level::Warn => writeln!(buf, "{}", message.yellow()),
+                Level::Info => writeln!(buf, "{message}"),
+                level => writeln!(buf, "[{level} {}] {message}", record.target()),
+            }
+        })
+        .init();
+}
+
 fn main_try() -> anyhow::Result<()> {
     let binary_name = std::env::current_exe()
         .map(|p| p.file_name().map(|s| s.to_string_lossy().to_string()))
@@ -70,26 +131,156 @@ fn main_try() -> anyhow::Result<()> {
             clap::Command::new("pack")
                 .visible_alias("release")
                 .about("Pack changelog entries to a changelog section")
-                .arg(optional_channel_arg.clone()),
+                .after_help(format!("Exit codes with --yes or --version: {EXIT_NOTHING_TO_RELEASE} = nothing to release, {EXIT_VERSION_EXISTS} = version already exists, 2 = invalid arguments."))
+                .arg(optional_channel_arg.clone())
+                .arg(clap::Arg::new("VERSION")
+                    .short('V')
+                    .long("version")
+                    .help("Version to release - skips the version prompt. Use \"auto\" to take it from the version sources (e.g. Cargo.toml)")
+                    .env(ENV_VERSION)
+                    .value_parser(NonEmptyStringValueParser::new()))
+                .arg(clap::Arg::new("YES")
+                    .short('y')
+                    .long("yes")
+                    .help("Do not ask any questions. Requires --version, and --channel if it can't be detected from the branch")
+                    .action(clap::ArgAction::SetTrue))
+                .arg(clap::Arg::new("INTEGRATION")
+                    .long("integration")
+                    .value_name("ID")
+                    .help("Run an integration (e.g. youtrack) without asking. Can be repeated")
+                    .action(clap::ArgAction::Append))
+                .arg(clap::Arg::new("SKIP_INTEGRATION")
+                    .long("skip-integration")
+                    .value_name("ID")
+                    .help("Skip an integration. Can be repeated")
+                    .action(clap::ArgAction::Append))
+                .arg(clap::Arg::new("YOUTRACK")
+                    .long("youtrack")
+                    .help("Same as --integration youtrack")
+                    .action(clap::ArgAction::SetTrue))
+                .arg(clap::Arg::new("NO_YOUTRACK")
+                    .long("no-youtrack")
+                    .help("Same as --skip-integration youtrack")
+                    .action(clap::ArgAction::SetTrue)
+                    .conflicts_with("YOUTRACK"))
+                .arg(clap::Arg::new("DRY_RUN")
+                    .long("dry-run")
+                    .help("Show the release and the version file changes, but do not write anything")
+                    .action(clap::ArgAction::SetTrue)),
         )
         .subcommand(
             clap::Command::new("status")
                 .about("Show outstanding change entries on the current channel (or specified channel)")
-                .arg(optional_channel_arg),
+                .arg(optional_channel_arg.clone()),
         )
         .subcommand(clap::Command::new("add")
             .visible_alias("log")
-            .about("Add a changelog entry on the current branch"))
-        // .subcommand(clap::Command::new("flush")
-        //     .about("Remove all changelog entries that were already released on all channels - clean up the changelog dir. Use e.g. when making a major release where all channel branches are merged."))
-        // .subcommand(clap::Command::new("status")
-        //     .about("Show changelog entries currently waiting for release on the current channel"))
+            .about("Add a changelog entry on the current branch")
+            .after_help("Values not given as options are asked for interactively. With --message or --stdin, no questions are asked - the name is taken from the branch if not given.")
+            .arg(clap::Arg::new("NAME")
+                .short('n')
+                .long("name")
+                .help("Entry name, used as a filename without extension")
+                .value_parser(NonEmptyStringValueParser::new()))
+            .arg(clap::Arg::new("SECTION")
+                .short('s')
+                .long("section")
+                .help("Changelog section for the messages that follow (repeatable)")
+                .value_parser(NonEmptyStringValueParser::new())
+                .action(clap::ArgAction::Append))
+            .arg(clap::Arg::new("MESSAGE")
+                .short('m')
+                .long("message")
+                .help("Entry line, added under the preceding --section (repeatable)")
+                .value_parser(NonEmptyStringValueParser::new())
+                .action(clap::ArgAction::Append))
+            .arg(clap::Arg::new("STDIN")
+                .long("stdin")
+                .help("Read the whole entry text (Markdown) from stdin")
+                .action(clap::ArgAction::SetTrue)
+                .conflicts_with_all(["SECTION", "MESSAGE"])))
+        .subcommand(clap::Command::new("flush")
+            .about("Remove all changelog entries that were already released on all channels - clean up the changelog dir. Use e.g. when making a major release where all channel branches are merged.")
+            .arg(clap::Arg::new("ARCHIVE")
+                .long("archive")
+                .help("Move the entries to the archive folder instead of deleting them")
+                .action(clap::ArgAction::SetTrue)))
+        .subcommand(
+            clap::Command::new("regenerate")
+                .about("Rebuild changelog files from the releases recorded in the store (all channels, or the specified channel)")
+                .arg(optional_channel_arg.clone())
+                .arg(clap::Arg::new("CHECK")
+                    .long("check")
+                    .help("Only report if the changelog files differ from the store, do not write anything")
+                    .action(clap::ArgAction::SetTrue)),
+        )
+        .subcommand(
+            clap::Command::new("import")
+                .about("Import releases from an existing Keep-a-Changelog style file into the store (default channel, or the specified channel)")
+                .arg(clap::Arg::new("FILE")
+                    .help("Changelog file to import")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .required(true))
+                .arg(optional_channel_arg.clone()),
+        )
+        .subcommand(
+            clap::Command::new("from-commits")
+                .about("Generate changelog entries from Conventional Commits since the last release (of the detected channel, or the specified channel)")
+                .arg(optional_channel_arg)
+                .arg(clap::Arg::new("SINCE")
+                    .long("since")
+                    .help("Git ref to start from, instead of the commit of the last release")
+                    .value_parser(NonEmptyStringValueParser::new()))
+                .arg(clap::Arg::new("MERGES")
+                    .long("merges")
+                    .help("Create one entry per merge commit, combining the commits it merged")
+                    .action(clap::ArgAction::SetTrue))
+                .arg(clap::Arg::new("DRY_RUN")
+                    .long("dry-run")
+                    .help("Only print the entries, do not write them")
+                    .action(clap::ArgAction::SetTrue)),
+        )
+        .subcommand(
+            clap::Command::new("config")
+                .about("Inspect the configuration")
+                .subcommand_required(true)
+                .subcommand(clap::Command::new("show")
+                    .about("Print the merged configuration (project file, user file and CLPACK_* variables) and where each value came from"))
+                .subcommand(clap::Command::new("validate")
+                    .about("Check the merged configuration - compile all patterns, check channel references and report all problems at once"))
+                .subcommand(clap::Command::new("schema")
+                    .about("Print the JSON Schema of the config file, for editor autocompletion and validation")),
+        )
+        .subcommand(clap::Command::new("check")
+            .about("Check that the current branch adds a changelog entry - for use in CI on merge requests")
+            .arg(clap::Arg::new("BASE")
+                .short('b')
+                .long("base")
+                .help("Git ref to compare with. Defaults to the merge request target from GitLab CI or GitHub Actions variables")
+                .value_parser(NonEmptyStringValueParser::new()))
+            .arg(clap::Arg::new("LABELS")
+                .long("labels")
+                .help("Comma-separated merge request labels, used to skip the check")
+                .env("CI_MERGE_REQUEST_LABELS")
+                .value_delimiter(',')))
         .subcommand_required(false)
         .arg(clap::Arg::new("CONFIG")
             .short('c')
             .long("config")
             .value_parser(NonEmptyStringValueParser::new())
             .required(false))
+        .arg(clap::Arg::new("PACKAGE")
+            .short('p')
+            .long("package")
+            .help("Package of a monorepo to work on, instead of detecting it from the current directory or the changed files")
+            .env(ENV_PACKAGE)
+            .value_parser(NonEmptyStringValueParser::new())
+            .global(true))
+        .arg(clap::Arg::new("BRANCH")
+            .long("branch")
+            .help("Git branch name to use for channel, issue and version detection, instead of detecting it")
+            .value_parser(NonEmptyStringValueParser::new())
+            .global(true))
         .after_help(
             "Call with no arguments to create a changelog entry (same as the \"add\" subcommand).",
         )
@@ -97,15 +288,17 @@ fn main_try() -> anyhow::Result<()> {
 
     let specified_config_file = args.get_one::<String>("CONFIG").map(|s| s.as_str());
 
-    let config_file_name: &str = specified_config_file.unwrap_or("clpack.toml");
-
-    // eprintln!("Loading configuration from {}", config_file_name);
-
-    let Ok(root) = std::env::current_dir() else {
+    let Ok(cwd) = std::env::current_dir() else {
         bail!("Failed to get current directory - is it deleted / inaccessible?");
     };
 
-    let config_path = root.join(&config_file_name); // if absolute, it is replaced by it
+    let root = find_project_root(&cwd);
+
+    // A config file given on the command line is relative to the current directory, like any other path argument
+    let config_path = match specified_config_file {
+        Some(file) => cwd.join(file), // if absolute, it is replaced by it
+        None => root.join(CONFIG_FILE_NAME),
+    };
 
     if let Some(("init", _)) = args.subcommand() {
         return cl_init(ClInit {
@@ -116,43 +309,149 @@ fn main_try() -> anyhow::Result<()> {
     }
 
     // Load and parse config
-    let config: Config = if let Ok(config_file_content) = std::fs::read_to_string(&config_path) {
-        match toml::from_str(&config_file_content) {
-            Ok(config) => config,
-            Err(e) => {
-                bail!(
-                    "Failed to parse config file ({}): {}",
-                    config_path.display(),
-                    e
-                );
-            }
-        }
-    } else if specified_config_file.is_some() {
+    let project_config_exists = config_path.is_file();
+    if !project_config_exists && specified_config_file.is_some() {
         // Failed to load config the user specifically asked for - make it an error
         bail!("Failed to load config file at {}", config_path.display());
-    } else {
-        Default::default()
-    };
+    }
+
+    let loaded_config = Config::load(&config_path, user_config_path().as_deref())?;
+
+    if !project_config_exists && !matches!(args.subcommand(), Some(("config", _))) {
+        eprintln!(
+            "{}",
+            format!(
+                "No {CONFIG_FILE_NAME} found in {} or its parents, using the default config. Run `{binary_name} init` to create it.",
+                cwd.display()
+            )
+            .yellow()
+        );
+    }
 
-    let ctx = AppContext {
+    // The config subcommands report problems themselves, and must work with a broken config
+    if !matches!(args.subcommand(), Some(("config", _))) {
+        let report = loaded_config.config.validate();
+        print_report(&report);
+        if !report.errors.is_empty() {
+            bail!(
+                "The config has {} error(s), fix them first. Run `{binary_name} config validate` to check it again.",
+                report.errors.len()
+            );
+        }
+    }
+
+    let mut ctx = AppContext {
         binary_name,
-        config,
+        config: loaded_config.config.clone(),
         root,
+        branch: args.get_one("BRANCH").cloned(),
+        package: None,
     };
 
+    // Flushing works on all packages at once - entries may be shared by several of them
+    if !ctx.config.packages.is_empty() && !matches!(args.subcommand(), Some(("flush", _))) {
+        let package = match detect_package(&ctx, args.get_one("PACKAGE").cloned(), &cwd)? {
+            Some(package) => Some(package),
+            // Entries may belong to several packages, `add` asks for them itself
+            None if matches!(
+                args.subcommand(),
+                None | Some(("add" | "check" | "config", _))
+            ) =>
+            {
+                None
+            }
+            None => {
+                let yes = args
+                    .subcommand()
+                    .and_then(|(_, subargs)| subargs.try_get_one::<bool>("YES").ok().flatten())
+                    .copied()
+                    .unwrap_or_default();
+                Some(resolve_package(&ctx, !yes && ui::is_interactive())?)
+            }
+        };
+        if let Some(package) = package {
+            println!("Package: {}", package.green().bold());
+            ctx.select_package(package)?;
+        }
+    }
+
     // eprintln!("AppCtx: {:?}", ctx);
 
     match args.subcommand() {
         Some(("pack", subargs)) => {
-            let channel: Option<ChannelName> = subargs.get_one("CHANNEL").cloned();
-            cl_pack(ctx, channel)?;
+            let mut integrations = IndexMap::new();
+            for (id, run) in [("INTEGRATION", true), ("SKIP_INTEGRATION", false)] {
+                for name in subargs.get_many::<String>(id).into_iter().flatten() {
+                    integrations.insert(name.clone(), run);
+                }
+            }
+            if subargs.get_flag("YOUTRACK") {
+                integrations.insert("youtrack".to_string(), true);
+            } else if subargs.get_flag("NO_YOUTRACK") {
+                integrations.insert("youtrack".to_string(), false);
+            }
+            cl_pack(
+                ctx,
+                ClPack {
+                    channel: subargs.get_one("CHANNEL").cloned(),
+                    version: subargs.get_one("VERSION").cloned(),
+                    yes: subargs.get_flag("YES"),
+                    integrations,
+                    dry_run: subargs.get_flag("DRY_RUN"),
+                },
+            )?;
         }
         Some(("status", subargs)) => {
             let channel: Option<ChannelName> = subargs.get_one("CHANNEL").cloned();
             cl_status(ctx, channel)?;
         }
-        None | Some(("add", _)) => cl_log(ctx)?,
-        // TODO: status, flush
+        Some(("regenerate", subargs)) => {
+            let channel: Option<ChannelName> = subargs.get_one("CHANNEL").cloned();
+            cl_regenerate(ctx, channel, subargs.get_flag("CHECK"))?;
+        }
+        Some(("import", subargs)) => {
+            cl_import(
+                ctx,
+                ClImport {
+                    file: subargs.get_one::<PathBuf>("FILE").cloned().unwrap(),
+                    channel: subargs.get_one("CHANNEL").cloned(),
+                },
+            )?;
+        }
+        Some(("from-commits", subargs)) => {
+            cl_from_commits(
+                ctx,
+                ClFromCommits {
+                    channel: subargs.get_one("CHANNEL").cloned(),
+                    since: subargs.get_one("SINCE").cloned(),
+                    merges: subargs.get_flag("MERGES"),
+                    dry_run: subargs.get_flag("DRY_RUN"),
+                },
+            )?;
+        }
+        Some(("config", subargs)) => match subargs.subcommand() {
+            Some(("show", _)) => cl_config_show(&loaded_config)?,
+            Some(("validate", _)) => cl_config_validate(&loaded_config)?,
+            Some(("schema", _)) => cl_config_schema()?,
+            _ => unreachable!("subcommand is required"),
+        },
+        Some(("check", subargs)) => {
+            cl_check(
+                ctx,
+                ClCheck {
+                    base: subargs.get_one("BASE").cloned(),
+                    labels: subargs
+                        .get_many::<String>("LABELS")
+                        .map(|labels| labels.cloned().collect())
+                        .unwrap_or_default(),
+                },
+            )?;
+        }
+        Some(("flush", subargs)) => {
+            cl_flush(ctx, subargs.get_flag("ARCHIVE"))?;
+        }
+        Some(("add", subargs)) => cl_log(ctx, parse_log_args(subargs))?,
+        None => cl_log(ctx, ClLog::default())?,
         Some((other, _)) => {
             bail!("Subcommand {other} is not implemented yet");
         }
@@ -160,3 +459,60 @@ fn main_try() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Choose the package to work on, if it was not given or found from the current directory -
+/// the one changed on the branch if there is just one, otherwise ask (if allowed)
+pub fn resolve_package(ctx: &AppContext, interactive: bool) -> anyhow::Result<PackageName> {
+    // Outside git, there is just nothing to detect
+    let changed = changed_packages(ctx).unwrap_or_default();
+    if let [package] = changed.as_slice() {
+        return Ok(package.clone());
+    }
+
+    if !interactive {
+        bail!(
+            "Package could not be detected from the current directory or the changes on the branch, use --package."
+        );
+    }
+
+    let packages = ctx.config.packages.keys().collect::<Vec<_>>();
+    let starting = changed
+        .first()
+        .and_then(|first| ctx.config.packages.get_index_of(first));
+    Ok(ui::select("Package?", packages, starting)?.clone())
+}
+
+/// Collect options of the "add" subcommand.
+///
+/// Messages are grouped under the section that precedes them on the command line.
+fn parse_log_args(args: &clap::ArgMatches) -> ClLog {
+    let mut tokens = Vec::<(usize, bool, String)>::new();
+    for (id, is_section) in [("SECTION", true), ("MESSAGE", false)] {
+        if let (Some(indices), Some(values)) = (args.indices_of(id), args.get_many::<String>(id)) {
+            for (index, value) in indices.zip(values) {
+                tokens.push((index, is_section, value.clone()));
+            }
+        }
+    }
+    tokens.sort_by_key(|(index, _, _)| *index);
+
+    let mut sections = IndexMap::<String, Vec<String>>::new();
+    let mut current_section = String::new();
+    for (_, is_section, value) in tokens {
+        if is_section {
+            sections.entry(value.clone()).or_default();
+            current_section = value;
+        } else {
+            sections
+                .entry(current_section.clone())
+                .or_default()
+                .push(value);
+        }
+    }
+
+    ClLog {
+        name: args.get_one::<String>("NAME").cloned(),
+        sections,
+        stdin: args.get_flag("STDIN"),
+    }
+}
@@ -0,0 +1,107 @@
+//! Monorepo packages - finding which package to work on
+
+use crate::AppContext;
+use crate::config::{Config, PackageName};
+use crate::git::{changed_files, get_branch_name};
+use anyhow::bail;
+use std::path::{Component, Path, PathBuf};
+
+/// Find the package containing a path (relative to the project root). With nested packages, the innermost one wins.
+pub fn package_of_path(config: &Config, path: &Path) -> Option<PackageName> {
+    let path = normalize(path);
+    config
+        .packages
+        .iter()
+        .map(|(name, package)| (name, normalize(Path::new(&package.path))))
+        .filter(|(_, package_path)| path.starts_with(package_path))
+        .max_by_key(|(_, package_path)| package_path.components().count())
+        .map(|(name, _)| name.clone())
+}
+
+/// Drop `.` components, so that `./core` and `core` are the same
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Package given on the command line, or the one containing the current directory
+pub fn detect_package(
+    ctx: &AppContext,
+    explicit: Option<PackageName>,
+    cwd: &Path,
+) -> anyhow::Result<Option<PackageName>> {
+    if let Some(package) = explicit {
+        if !ctx.config.packages.contains_key(&package) {
+            bail!("No such package: {package}");
+        }
+        return Ok(Some(package));
+    }
+
+    Ok(cwd
+        .strip_prefix(&ctx.root)
+        .ok()
+        .and_then(|path| package_of_path(&ctx.config, path)))
+}
+
+/// Packages with files changed on the current branch, in config order.
+/// Changes in the changelog data folder are not counted.
+pub fn changed_packages(ctx: &AppContext) -> anyhow::Result<Vec<PackageName>> {
+    let Some(branch) = get_branch_name(ctx) else {
+        return Ok(vec![]);
+    };
+
+    let data_folder = Path::new(&ctx.config.data_folder);
+    let mut found = vec![];
+    for file in changed_files(ctx, &branch)? {
+        let path = Path::new(&file);
+        if path.starts_with(data_folder) {
+            continue;
+        }
+        if let Some(package) = package_of_path(&ctx.config, path)
+            && !found.contains(&package)
+        {
+            found.push(package);
+        }
+    }
+
+    Ok(ctx
+        .config
+        .packages
+        .keys()
+        .filter(|name| found.contains(name))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_package_of_path() {
+        let config: Config = toml::from_str(
+            r#"
+[packages.core]
+path = "crates/core"
+
+[packages.core-macros]
+path = "./crates/core/macros"
+
+[packages.app]
+path = "app/"
+"#,
+        )
+        .unwrap();
+
+        let package = |path: &str| package_of_path(&config, Path::new(path));
+        assert_eq!(package("crates/core/src/lib.rs").as_deref(), Some("core"));
+        assert_eq!(
+            package("crates/core/macros/src/lib.rs").as_deref(),
+            Some("core-macros")
+        );
+        assert_eq!(package("app").as_deref(), Some("app"));
+        assert_eq!(package("crates/core-extra/lib.rs"), None);
+        assert_eq!(package("README.md"), None);
+    }
+}
@@ -1,19 +1,25 @@
+//! Changelog data folder - entries, releases of each channel and the changelog files
+
 use crate::AppContext;
-use crate::config::{ChannelName, Config, EntryName, VersionName};
+use crate::config::{ChannelName, Config, EntryName, PackageName, VersionName};
+use crate::entry::Entry;
+use crate::git::{get_branch_name, get_head_commit, get_user_name};
 use anyhow::{Context, bail};
-use colored::Colorize;
+use chrono::{DateTime, FixedOffset, Timelike};
 use faccess::PathExt;
 use indexmap::IndexMap;
 use serde::{Deserialize, Serialize};
-use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::HashSet;
 use std::fs::{File, OpenOptions, read_to_string};
-use std::io::{BufRead, BufReader, Write};
-use std::path::{Path, PathBuf};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
 
 const DIR_ENTRIES: &str = "entries";
 const DIR_CHANNELS: &str = "channels";
+const DIR_ARCHIVE: &str = "archive";
 
-const SUPPORTED_FORMAT_VERSION: usize = 1;
+const SUPPORTED_FORMAT_VERSION: usize = 2;
 
 #[derive(Debug, Deserialize, Serialize)]
 struct Manifest {
@@ -21,6 +27,26 @@ struct Manifest {
     format_version: usize,
 }
 
+impl Manifest {
+    /// Write the manifest file, replacing it if it exists
+    fn write_to_file(&self, manifest_path: &Path) -> anyhow::Result<()> {
+        let manifest_file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(manifest_path)
+            .with_context(|| {
+                format!(
+                    "Opening manifest file for writing: {}",
+                    manifest_path.display()
+                )
+            })?;
+
+        serde_json::to_writer_pretty(manifest_file, self)
+            .with_context(|| format!("Writing manifest file: {}", manifest_path.display()))
+    }
+}
+
 /// Changelog store struct
 pub struct Store<'a> {
     /// App context, including config
@@ -29,9 +55,12 @@ pub struct Store<'a> {
     store_path: PathBuf,
     /// Loaded version history for all channels
     versions: IndexMap<ChannelName, ChannelReleaseStore>,
+    /// Format version in the manifest file - an older one is migrated before the store is written to
+    format_version: Cell<usize>,
 }
 
 impl<'a> Store<'a> {
+    /// Open the data folder of the project. With `init`, the data folder may be created.
     pub fn new(ctx: &'a AppContext, init: bool) -> anyhow::Result<Self> {
         let store_path = ctx.root.join(&ctx.config.data_folder);
 
@@ -57,6 +86,7 @@ impl<'a> Store<'a> {
         }
 
         let manifest_path = store_path.join("manifest.json");
+        let mut format_version = SUPPORTED_FORMAT_VERSION;
         if manifest_path.is_file() {
             let manifest_file = OpenOptions::new()
                 .read(true)
@@ -66,7 +96,11 @@ impl<'a> Store<'a> {
             let manifest: Manifest = serde_json::from_reader(manifest_file)
                 .with_context(|| format!("Reading manifest file: {}", manifest_path.display()))?;
 
-            if manifest.format_version != 1 {
+            if manifest.format_version == 1 {
+                // Format 2 only adds optional fields to the releases in channel files,
+                // so those can be used as they are. The manifest is updated on the first write.
+                format_version = 1;
+            } else if manifest.format_version != SUPPORTED_FORMAT_VERSION {
                 bail!(
                     "clpack store is in format {}. This version of clpack requires format {}",
                     manifest.format_version,
@@ -74,29 +108,18 @@ impl<'a> Store<'a> {
                 );
             }
         } else {
-            println!("Creating clpack manifest file: {}", manifest_path.display());
-            let manifest_file = OpenOptions::new()
-                .create_new(true)
-                .write(true)
-                .open(&manifest_path)
-                .with_context(|| {
-                    format!(
-                        "Opening manifest file for writing: {}",
-                        manifest_path.display()
-                    )
-                })?;
-
-            let manifest = Manifest {
+            log::info!("Creating clpack manifest file: {}", manifest_path.display());
+            Manifest {
                 format_version: SUPPORTED_FORMAT_VERSION,
-            };
-            serde_json::to_writer_pretty(manifest_file, &manifest)
-                .with_context(|| format!("Writing manifest file: {}", manifest_path.display()))?;
+            }
+            .write_to_file(&manifest_path)?;
         }
 
         let mut store = Self {
             store_path,
             ctx,
             versions: IndexMap::new(),
+            format_version: Cell::new(format_version),
         };
 
         store.ensure_internal_subdirs_exist()?;
@@ -105,6 +128,25 @@ impl<'a> Store<'a> {
         Ok(store)
     }
 
+    /// Update the manifest of a store in an older format. Only commands that write to the store do this,
+    /// so read-only ones (e.g. in CI) leave the files unchanged.
+    fn migrate(&self) -> anyhow::Result<()> {
+        let format_version = self.format_version.get();
+        if format_version == SUPPORTED_FORMAT_VERSION {
+            return Ok(());
+        }
+        log::info!(
+            "Migrating clpack store from format {format_version} to {SUPPORTED_FORMAT_VERSION}: {}",
+            self.store_path.display()
+        );
+        Manifest {
+            format_version: SUPPORTED_FORMAT_VERSION,
+        }
+        .write_to_file(&self.store_path.join("manifest.json"))?;
+        self.format_version.set(SUPPORTED_FORMAT_VERSION);
+        Ok(())
+    }
+
     /// Build a log entry file path.
     /// This is a file in the entries storage
     fn make_entry_path(&self, filename: &str) -> PathBuf {
@@ -119,21 +161,60 @@ impl<'a> Store<'a> {
         self.make_entry_path(name).exists()
     }
 
-    /// Load release lists for all channels
-    fn load_versions(&mut self) -> anyhow::Result<()> {
+    /// Path of the file with the releases of a channel. Packages of a monorepo have theirs in a subfolder.
+    fn channel_file_path(&self, package: Option<&str>, channel: &ChannelName) -> PathBuf {
         let channels_dir = self.store_path.join(DIR_CHANNELS);
+        match package {
+            Some(package) => channels_dir.join(package),
+            None => channels_dir,
+        }
+        .join(format!("{channel}.json"))
+    }
 
-        for ch in self.ctx.config.channels.keys() {
-            let channel_file = channels_dir.join(format!("{}.json", ch));
-            self.versions.insert(
-                ch.clone(),
-                ChannelReleaseStore::load(channel_file, ch.clone())?,
-            );
+    /// Load release lists for all channels.
+    ///
+    /// In a monorepo without a selected package, the channels of all packages are loaded,
+    /// keyed `package/channel` - that is only useful for flushing, which looks at all of them.
+    fn load_versions(&mut self) -> anyhow::Result<()> {
+        let config = &self.ctx.config;
+        if config.packages.is_empty() || self.ctx.package.is_some() {
+            for ch in config.channels.keys() {
+                self.load_channel(ch.clone(), self.ctx.package.clone(), ch.clone())?;
+            }
+        } else {
+            for package in config.packages.keys() {
+                for ch in config.for_package(package)?.channels.keys() {
+                    self.load_channel(
+                        format!("{package}/{ch}"),
+                        Some(package.clone()),
+                        ch.clone(),
+                    )?;
+                }
+            }
         }
 
         Ok(())
     }
 
+    /// Load the release list of a channel, under the given key
+    fn load_channel(
+        &mut self,
+        key: String,
+        package: Option<PackageName>,
+        channel: ChannelName,
+    ) -> anyhow::Result<()> {
+        let channel_file = self.channel_file_path(package.as_deref(), &channel);
+        if let Some(dir) = channel_file.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Creating channels dir: {}", dir.display()))?;
+        }
+        self.versions.insert(
+            key,
+            ChannelReleaseStore::load(channel_file, package, channel)?,
+        );
+        Ok(())
+    }
+
     /// Check and create internal subdirs for the clpack system
     pub fn ensure_internal_subdirs_exist(&self) -> anyhow::Result<()> {
         self.ensure_subdir_exists(DIR_ENTRIES, true)?;
@@ -171,18 +252,54 @@ impl<'a> Store<'a> {
         Ok(())
     }
 
+    /// Check that an entry name is a plain file name, in the subfolder of a package if it is prefixed by one.
+    pub fn check_entry_name(&self, name: &str) -> anyhow::Result<()> {
+        let basename = match name.split_once('/') {
+            Some((package, rest)) if self.ctx.config.packages.contains_key(package) => rest,
+            _ => name,
+        };
+        let mut components = Path::new(basename).components();
+        if basename.contains(['/', '\\'])
+            || !matches!(components.next(), Some(Component::Normal(_)))
+            || components.next().is_some()
+        {
+            bail!(
+                "Invalid entry name \"{name}\" - it is used as a file name, path separators are not allowed."
+            );
+        }
+        Ok(())
+    }
+
     /// Create a changelog entry file and write content to it
     pub fn create_entry(&self, name: EntryName, content: String) -> anyhow::Result<()> {
+        self.check_entry_name(&name)?;
+        self.migrate()?;
         let path = self.make_entry_path(name.as_str());
-        let mut file = OpenOptions::new().write(true).create(true).open(&path)?;
+        // Package subfolder
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Creating entries dir: {}", dir.display()))?;
+        }
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
 
-        println!("Writing changelog entry to file: {}", path.display());
+        log::info!("Writing changelog entry to file: {}", path.display());
 
         file.write_all(content.as_bytes())
             .with_context(|| format!("Writing file {}", path.display()))?;
         Ok(())
     }
 
+    /// Remove a changelog entry file, e.g. when a command that created it failed. Filename is passed without extension.
+    pub fn remove_entry(&self, name: &str) -> anyhow::Result<()> {
+        let path = self.make_entry_path(name);
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Removing entry file {}", path.display()))
+    }
+
     /// Check if a version was already released (on any channel) - prevents the user from making a mistake in version naming
     pub fn version_exists(&self, version: &str) -> bool {
         for v in self.versions.values() {
@@ -199,33 +316,97 @@ impl<'a> Store<'a> {
             bail!("Channel {channel} does not exist.");
         };
 
-        store.find_unreleased_entries(self.store_path.join(DIR_ENTRIES))
+        store.find_unreleased_entries(&self.list_entry_files()?, self.ctx.package.as_deref())
+    }
+
+    /// Names of the entry files picked up when packing - in the entries dir and in the subfolders of packages
+    pub fn entry_names(&self) -> anyhow::Result<Vec<EntryName>> {
+        Ok(self
+            .list_entry_files()?
+            .into_iter()
+            .map(|file| file.name)
+            .collect())
+    }
+
+    /// List entry files in the entries dir, and in the subfolders of packages
+    fn list_entry_files(&self) -> anyhow::Result<Vec<EntryFile>> {
+        let entries_dir = self.store_path.join(DIR_ENTRIES);
+        let packages = &self.ctx.config.packages;
+
+        let mut found = vec![];
+        let dirs = std::iter::once((None, entries_dir.clone())).chain(
+            packages
+                .keys()
+                .map(|package| (Some(package.clone()), entries_dir.join(package)))
+                .filter(|(_, dir)| dir.is_dir()),
+        );
+        for (folder, dir) in dirs {
+            for item in dir.read_dir()? {
+                let item = item?;
+
+                let fname_os = item.file_name();
+                let fname = fname_os.into_string().map_err(|_| {
+                    anyhow::anyhow!("Failed to parse file name: {}", item.path().display())
+                })?;
+
+                let is_dir = item.metadata()?.is_dir();
+                if is_dir && folder.is_none() && packages.contains_key(&fname) {
+                    // Package subfolder, listed separately
+                    continue;
+                }
+
+                if is_dir || !fname.ends_with(".md") {
+                    if fname != ".gitkeep" {
+                        log::warn!(
+                            "Unexpected item in changelog entries dir: {}",
+                            item.path().display()
+                        );
+                    }
+                    continue;
+                }
+
+                let basename = fname.strip_suffix(".md").unwrap();
+                found.push(EntryFile {
+                    name: match &folder {
+                        Some(folder) => format!("{folder}/{basename}"),
+                        None => basename.to_string(),
+                    },
+                    path: item.path(),
+                    folder: folder.clone(),
+                });
+            }
+        }
+
+        Ok(found)
+    }
+
+    /// Prepare a release of the unreleased entries of a channel, with the current time and git metadata.
+    /// Nothing is written until it is passed to `create_release()`.
+    pub fn new_release(
+        &self,
+        channel: &ChannelName,
+        version: VersionName,
+    ) -> anyhow::Result<Release> {
+        let mut release = Release {
+            version,
+            entries: self.find_unreleased_changes(channel)?,
+            ..Default::default()
+        };
+        release.set_metadata(self.ctx);
+        Ok(release)
     }
 
     /// Create a release entry, write it to the releases buffer and to the file.
     pub fn create_release(&mut self, channel: ChannelName, release: Release) -> anyhow::Result<()> {
-        let rendered = self.render_release(&release)?;
+        let rendered = self.render_release(&channel, &release)?;
+        self.migrate()?;
 
         let Some(store) = self.versions.get_mut(&channel) else {
             bail!("Channel {channel} does not exist.");
         };
 
-        let config = &self.ctx.config;
-
-        let changelog_file = self.ctx.root.join(
-            if channel == config.default_channel {
-                Cow::Borrowed(config.changelog_file_default.as_str())
-            } else {
-                Cow::Owned(
-                    config
-                        .changelog_file_channel
-                        .replace("{channel}", &channel.to_lowercase())
-                        .replace("{CHANNEL}", &channel.to_uppercase())
-                        .replace("{Channel}", &ucfirst(&channel)),
-                )
-            }
-            .as_ref(),
-        );
+        let config = self.ctx.config.for_channel(&channel);
+        let changelog_file = changelog_file_path(self.ctx, &channel);
 
         if changelog_file.exists() {
             let changelog_file_content = read_to_string(&changelog_file)?;
@@ -249,7 +430,10 @@ impl<'a> Store<'a> {
                 .truncate(true)
                 .open(changelog_file)?;
 
-            outfile.write_all(format!("{}{}", config.changelog_header, rendered).as_bytes())?;
+            // The file ends with the last release, without a blank line
+            outfile.write_all(
+                format!("{}{}\n", config.changelog_header, rendered.trim_end()).as_bytes(),
+            )?;
         }
 
         store.add_version(release)?;
@@ -258,15 +442,392 @@ impl<'a> Store<'a> {
         Ok(())
     }
 
-    /// Render a release
-    pub fn render_release(&self, release: &Release) -> anyhow::Result<String> {
-        let config = &self.ctx.config;
-        release.render(self.store_path.join(DIR_ENTRIES), &config)
+    /// Add releases imported from an existing changelog file.
+    ///
+    /// They are older than anything packed by clpack, so they go before the existing releases.
+    /// Releases are given oldest first.
+    pub fn import_releases(
+        &mut self,
+        channel: &ChannelName,
+        releases: Vec<Release>,
+    ) -> anyhow::Result<()> {
+        self.migrate()?;
+        let Some(store) = self.versions.get_mut(channel) else {
+            bail!("Channel {channel} does not exist.");
+        };
+
+        for release in &releases {
+            if store.version_exists(&release.version) {
+                bail!(
+                    "Version {} already exists on channel {channel}",
+                    release.version
+                );
+            }
+        }
+
+        store.releases.splice(0..0, releases);
+        store.write_to_file()
+    }
+
+    /// Path of the changelog file of a channel
+    pub fn changelog_file_path(&self, channel: &ChannelName) -> PathBuf {
+        changelog_file_path(self.ctx, channel)
+    }
+
+    /// Files that make up a release on a channel - the changelog file, the channel file, the manifest
+    /// and the entries of the release
+    pub fn release_files(&self, channel: &ChannelName, release: &Release) -> Vec<PathBuf> {
+        let mut files = vec![
+            self.changelog_file_path(channel),
+            self.channel_file_path(self.ctx.package.as_deref(), channel),
+            self.store_path.join("manifest.json"),
+        ];
+        let entries_dir = self.release_entries_dir(release);
+        files.extend(
+            release
+                .entries
+                .iter()
+                .map(|name| entries_dir.join(format!("{name}.md"))),
+        );
+        files
+    }
+
+    /// Render the complete changelog file of a channel from the store, newest release first.
+    ///
+    /// Releases stored without a date (format 1) take the date from their header in `old_content`
+    /// (the current changelog file), if found there.
+    pub fn render_changelog(
+        &self,
+        channel: &ChannelName,
+        old_content: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let Some(store) = self.versions.get(channel) else {
+            bail!("Channel {channel} does not exist.");
+        };
+        let config = self.ctx.config.for_channel(channel);
+        let config = config.as_ref();
+
+        let mut buffer = config.changelog_header.clone();
+        for release in store.releases.iter().rev() {
+            let entries_dir = self.release_entries_dir(release);
+            if release.flushed.is_some() && !entries_dir.is_dir() {
+                // Flushed without archiving - keep the section as it is in the changelog file
+                let Some(section) = old_content
+                    .map(|old_content| find_release_section(config, &release.version, old_content))
+                    .transpose()?
+                    .flatten()
+                else {
+                    bail!(
+                        "Release {} on channel {channel} was flushed without archiving, its entries are no longer available and it was not found in the changelog file.",
+                        release.version
+                    );
+                };
+                buffer.push_str(&section);
+                continue;
+            }
+
+            let old_date = match (&release.date, old_content) {
+                (None, Some(old_content)) => {
+                    find_release_date(config, &release.version, old_content)?
+                }
+                _ => None,
+            };
+
+            if release.date.is_none() && old_date.is_none() {
+                log::warn!(
+                    "Release {} has no stored date and it was not found in the changelog file, using today's date.",
+                    release.version
+                );
+            }
+
+            let rendered = match old_date {
+                Some(date_text) => release.render_with_date(entries_dir, config, &date_text),
+                None => release.render(entries_dir, config),
+            }
+            .with_context(|| {
+                format!("Rendering release {} on channel {channel}", release.version)
+            })?;
+            buffer.push_str(&rendered);
+        }
+
+        // Releases are separated by a blank line, the file ends without one
+        let mut buffer = buffer.trim_end().to_string();
+        buffer.push('\n');
+        Ok(buffer)
+    }
+
+    /// Get the last release made on a channel
+    pub fn last_release(&self, channel: &ChannelName) -> Option<&Release> {
+        self.versions.get(channel)?.releases.last()
+    }
+
+    /// Check if a channel has any releases
+    pub fn has_releases(&self, channel: &ChannelName) -> bool {
+        self.versions
+            .get(channel)
+            .is_some_and(|store| !store.releases.is_empty())
+    }
+
+    /// Render a release, with the settings of the channel
+    pub fn render_release(
+        &self,
+        channel: &ChannelName,
+        release: &Release,
+    ) -> anyhow::Result<String> {
+        let config = self.ctx.config.for_channel(channel);
+        release.render(self.release_entries_dir(release), &config)
+    }
+
+    /// Load and parse all entries of a release, e.g. to read their metadata
+    pub fn load_release_entries(
+        &self,
+        release: &Release,
+    ) -> anyhow::Result<IndexMap<EntryName, Entry>> {
+        let entries_dir = self.release_entries_dir(release);
+        release
+            .entries
+            .iter()
+            .map(|name| {
+                Ok((
+                    name.clone(),
+                    Entry::load(entries_dir.join(format!("{name}.md")))?,
+                ))
+            })
+            .collect()
+    }
+
+    /// Get the folder where entry files of a release are stored
+    fn release_entries_dir(&self, release: &Release) -> PathBuf {
+        match release.flushed {
+            // Flushed entries can only be loaded if they were archived
+            Some(epoch) => self.make_archive_path(epoch),
+            None => self.store_path.join(DIR_ENTRIES),
+        }
+    }
+
+    /// Build path of the archive folder for entries flushed in the given epoch
+    fn make_archive_path(&self, epoch: u32) -> PathBuf {
+        self.store_path
+            .join(DIR_ARCHIVE)
+            .join(format!("epoch-{epoch}"))
+    }
+
+    /// Find entries that were released on all channels and can be removed from the entries dir.
+    /// In a monorepo, that is all channels of the packages the entry belongs to.
+    ///
+    /// An entry is only flushed together with all releases that contain it, so that each release
+    /// is either fully present or fully flushed. Entries shared with a release that still has
+    /// some entries pending on another channel are kept for the next flush.
+    pub fn plan_flush(&self) -> FlushPlan {
+        let released: Vec<HashSet<&EntryName>> = self
+            .versions
+            .values()
+            .map(|store| store.live_releases().flat_map(|rel| &rel.entries).collect())
+            .collect();
+
+        // Only the channels the entry is for matter - those in its front-matter,
+        // and in a monorepo, the channels of the packages the entry belongs to
+        let mut candidates: HashSet<&EntryName> = released
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|e| {
+                let entry = Entry::load(self.make_entry_path(e)).ok();
+                self.versions
+                    .values()
+                    .zip(&released)
+                    .filter(|(store, _)| Self::entry_is_for_store(e, entry.as_ref(), store))
+                    .all(|(_, released)| released.contains(e))
+            })
+            .collect();
+
+        // Drop entries pinned by a release that can't be flushed as a whole, until nothing changes
+        loop {
+            let mut changed = false;
+            for rel in self.versions.values().flat_map(|s| s.live_releases()) {
+                if !rel.entries.iter().all(|e| candidates.contains(e)) {
+                    for e in &rel.entries {
+                        changed |= candidates.remove(e);
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+
+        let mut releases = IndexMap::<ChannelName, Vec<VersionName>>::new();
+        for (channel, store) in &self.versions {
+            let flushed_versions: Vec<VersionName> = store
+                .live_releases()
+                .filter(|rel| rel.entries.iter().all(|e| candidates.contains(e)))
+                .map(|rel| rel.version.clone())
+                .collect();
+            if !flushed_versions.is_empty() {
+                releases.insert(channel.clone(), flushed_versions);
+            }
+        }
+
+        // Keep the order stable for display
+        let mut entries: Vec<EntryName> = candidates.into_iter().cloned().collect();
+        entries.sort();
+
+        let epoch = self
+            .versions
+            .values()
+            .map(|s| s.last_epoch())
+            .max()
+            .unwrap_or(0)
+            + 1;
+
+        FlushPlan {
+            epoch,
+            entries,
+            releases,
+        }
+    }
+
+    /// Check if an entry is meant to be released in a channel store - by its channels, and its package in a monorepo.
+    /// If the entry file is gone, only its folder tells.
+    fn entry_is_for_store(
+        name: &EntryName,
+        entry: Option<&Entry>,
+        store: &ChannelReleaseStore,
+    ) -> bool {
+        if let Some(package) = &store.package {
+            let folder = name.split_once('/').map(|(folder, _)| folder);
+            let belongs = match entry {
+                Some(entry) => entry.is_for_package(folder, package),
+                None => folder.is_none_or(|folder| folder == package),
+            };
+            if !belongs {
+                return false;
+            }
+        }
+        entry.is_none_or(|entry| entry.is_for_channel(&store.channel_name))
+    }
+
+    /// Remove (or archive) entry files as planned by `plan_flush()` and mark the releases as flushed
+    pub fn flush(&mut self, plan: &FlushPlan, archive: bool) -> anyhow::Result<()> {
+        self.migrate()?;
+        let entries_dir = self.store_path.join(DIR_ENTRIES);
+        let archive_dir = self.make_archive_path(plan.epoch);
+
+        if archive {
+            std::fs::create_dir_all(&archive_dir)
+                .with_context(|| format!("Creating archive dir: {}", archive_dir.display()))?;
+        }
+
+        for entry in &plan.entries {
+            let path = entries_dir.join(format!("{entry}.md"));
+            if !path.exists() {
+                // Deleted by hand already
+                continue;
+            }
+            if archive {
+                let target = archive_dir.join(format!("{entry}.md"));
+                if let Some(dir) = target.parent() {
+                    std::fs::create_dir_all(dir)
+                        .with_context(|| format!("Creating archive dir: {}", dir.display()))?;
+                }
+                std::fs::rename(&path, &target).with_context(|| {
+                    format!(
+                        "Moving entry {} to archive {}",
+                        path.display(),
+                        target.display()
+                    )
+                })?;
+            } else {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("Removing entry file {}", path.display()))?;
+            }
+        }
+
+        // Mark the releases only once the files are gone - a leftover entry file of a flushed
+        // release would show up as unreleased again
+        for (channel, versions) in &plan.releases {
+            let Some(store) = self.versions.get_mut(channel) else {
+                bail!("Channel {channel} does not exist.");
+            };
+            store.mark_flushed(versions, plan.epoch);
+            store.write_to_file()?;
+        }
+
+        Ok(())
     }
 }
 
+/// Result of `Store::plan_flush()`
+pub struct FlushPlan {
+    /// Epoch number recorded into the flushed releases
+    pub epoch: u32,
+    /// Entries to remove, released on all channels
+    pub entries: Vec<EntryName>,
+    /// Releases to mark as flushed, per channel
+    pub releases: IndexMap<ChannelName, Vec<VersionName>>,
+}
+
+/// Build path of the changelog file of a channel
+fn changelog_file_path(ctx: &AppContext, channel: &ChannelName) -> PathBuf {
+    ctx.root.join(ctx.config.changelog_file_name(channel))
+}
+
+/// Find the date of a release in rendered changelog text, by matching the `release_header` pattern.
+/// The date is returned as written in the file.
+fn find_release_date(
+    config: &Config,
+    version: &str,
+    changelog: &str,
+) -> anyhow::Result<Option<String>> {
+    if !config.release_header.contains("{DATE}") {
+        return Ok(None);
+    }
+
+    let pattern = format!(
+        "(?m)^## {}$",
+        regex::escape(&config.release_header)
+            .replace(&regex::escape("{VERSION}"), &regex::escape(version))
+            .replace(&regex::escape("{DATE}"), "(.+?)")
+    );
+    let pattern = regex::Regex::new(&pattern).context("Building release header pattern")?;
+
+    Ok(pattern
+        .captures(changelog)
+        .and_then(|c| c.get(1))
+        .map(|m| m.as_str().to_string()))
+}
+
+/// Find the rendered section of a release in changelog text - from its header up to the next release header.
+/// The section is returned with the trailing blank line, like `Release::render()` makes it.
+fn find_release_section(
+    config: &Config,
+    version: &str,
+    changelog: &str,
+) -> anyhow::Result<Option<String>> {
+    let pattern = format!(
+        "(?m)^## {}$",
+        regex::escape(&config.release_header)
+            .replace(&regex::escape("{VERSION}"), &regex::escape(version))
+            .replace(&regex::escape("{DATE}"), ".+?")
+    );
+    let pattern = regex::Regex::new(&pattern).context("Building release header pattern")?;
+
+    let Some(header) = pattern.find(changelog) else {
+        return Ok(None);
+    };
+    let rest = &changelog[header.end()..];
+    let end = rest
+        .match_indices("\n## ")
+        .next()
+        .map_or(rest.len(), |(pos, _)| pos + 1);
+    Ok(Some(format!(
+        "{}\n\n",
+        changelog[header.start()..header.end() + end].trim_end()
+    )))
+}
+
 /// Uppercase first char of a string
-fn ucfirst(input: &str) -> String {
+pub(crate) fn ucfirst(input: &str) -> String {
     let mut c = input.chars();
     match c.next() {
         Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
@@ -275,54 +836,76 @@ fn ucfirst(input: &str) -> String {
 }
 
 /// Summary of a release
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct Release {
     /// Name of the version
     pub version: VersionName,
     /// List of entries included in this version
     pub entries: Vec<EntryName>,
+    /// Epoch marker - set by `cl flush` when the entry files of this release were removed.
+    /// Entries of flushed releases are no longer matched against the entries dir, so their names may be reused.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flushed: Option<u32>,
+    /// Time of the release, used for the date in the rendered changelog (added in format 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<DateTime<FixedOffset>>,
+    /// Git commit (SHA) the release was packed from (added in format 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// Git branch the release was packed from (added in format 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    /// Who packed the release - git user name (added in format 2)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
 }
 
 impl Release {
+    /// Stamp the release with the current time, and the git commit, branch and user it is packed from
+    pub fn set_metadata(&mut self, ctx: &AppContext) {
+        self.date = chrono::Local::now().fixed_offset().with_nanosecond(0);
+        self.commit = get_head_commit(ctx);
+        self.branch = get_branch_name(ctx).map(|b| b.to_string());
+        self.author = get_user_name(ctx);
+    }
+
     /// Render the entry into a Markdown fragment, using h2 (##) as the title, h3 (###) for sections
     pub fn render(&self, entries_dir: impl AsRef<Path>, config: &Config) -> anyhow::Result<String> {
+        // Releases from format 1 have no date
+        let date = self
+            .date
+            .unwrap_or_else(|| chrono::Local::now().fixed_offset());
+        self.render_with_date(
+            entries_dir,
+            config,
+            &date.format(&config.date_format).to_string(),
+        )
+    }
+
+    /// Render the entry like `render()`, with the date already formatted
+    pub fn render_with_date(
+        &self,
+        entries_dir: impl AsRef<Path>,
+        config: &Config,
+        date_text: &str,
+    ) -> anyhow::Result<String> {
         let mut entries_per_section = IndexMap::<String, String>::new();
         let entries_dir = entries_dir.as_ref();
         let unnamed_section = "".to_string();
 
         for entry in &self.entries {
-            let entry_file = entries_dir.join(&format!("{entry}.md"));
-
-            if !entry_file.exists() || !entry_file.readable() {
-                bail!(
-                    "Changelog entry file missing or not readable: {}",
-                    entry_file.display()
-                );
+            let parsed = Entry::load(entries_dir.join(format!("{entry}.md")))?;
+            if parsed.meta.internal {
+                // Part of the release, but not shown in the changelog
+                continue;
             }
 
-            let file = OpenOptions::new().read(true).open(&entry_file)?;
-            let reader = BufReader::new(file);
-
-            let mut current_section = unnamed_section.clone();
-            for line in reader.lines() {
-                let line = line?;
-                let line = line.trim_end();
-                let line_trimmed = line.trim();
-                if line_trimmed.is_empty() {
-                    continue;
-                }
-                if line_trimmed.starts_with('#') {
-                    // It is a section name
-                    current_section = line
-                        .trim_start_matches(|c| c == '#' || c == ' ')
-                        .to_string();
+            for (section, content) in parsed.sections {
+                if let Some(buffer) = entries_per_section.get_mut(&section) {
+                    buffer.push('\n');
+                    buffer.push_str(&content);
                 } else {
-                    if let Some(buffer) = entries_per_section.get_mut(&current_section) {
-                        buffer.push('\n');
-                        buffer.push_str(&line);
-                    } else {
-                        entries_per_section.insert(current_section.clone(), line.to_string());
-                    }
+                    entries_per_section.insert(section, content);
                 }
             }
         }
@@ -344,13 +927,12 @@ impl Release {
             reordered_sections.push((section_name, content));
         }
 
-        let date = chrono::Local::now();
         let mut buffer = format!(
             "## {}\n",
             config
                 .release_header
                 .replace("{VERSION}", &self.version)
-                .replace("{DATE}", &date.format(&config.date_format).to_string())
+                .replace("{DATE}", date_text)
         );
 
         for (section_name, content) in reordered_sections {
@@ -358,10 +940,10 @@ impl Release {
                 buffer.push_str(&format!("\n### {}\n", section_name));
             }
             buffer.push_str(content.trim_end());
-            buffer.push_str("\n");
+            buffer.push('\n');
         }
 
-        buffer.push_str("\n");
+        buffer.push('\n');
         Ok(buffer)
     }
 }
@@ -373,20 +955,26 @@ type ReleaseList = Vec<Release>;
 struct ChannelReleaseStore {
     /// File where the list of versions is stored
     backing_file: PathBuf,
-    /// Name of the channel, for error messages
+    /// Name of the channel, for error messages and entry channel filters
     channel_name: ChannelName,
+    /// Package of a monorepo the channel belongs to
+    package: Option<PackageName>,
     /// List of releases, load from the file
     releases: ReleaseList,
 }
 
 impl ChannelReleaseStore {
     /// Load from a versions file
-    fn load(releases_file: PathBuf, channel_name: ChannelName) -> anyhow::Result<Self> {
+    fn load(
+        releases_file: PathBuf,
+        package: Option<PackageName>,
+        channel_name: ChannelName,
+    ) -> anyhow::Result<Self> {
         let releases = if !releases_file.exists() {
             // File did not exist yet, create it - this catches error with write access early
             let mut f = OpenOptions::new()
                 .write(true)
-                .create(true)
+                .create_new(true)
                 .open(&releases_file)
                 .with_context(|| {
                     format!("Failed to open channel file: {}", releases_file.display())
@@ -413,10 +1001,25 @@ impl ChannelReleaseStore {
         Ok(Self {
             backing_file: releases_file,
             channel_name,
+            package,
             releases,
         })
     }
 
+    /// Releases not yet flushed - their entry files should still be present in the entries dir
+    fn live_releases(&self) -> impl Iterator<Item = &Release> {
+        self.releases.iter().filter(|rel| rel.flushed.is_none())
+    }
+
+    /// Highest flush epoch recorded in this channel (0 if never flushed)
+    fn last_epoch(&self) -> u32 {
+        self.releases
+            .iter()
+            .filter_map(|rel| rel.flushed)
+            .max()
+            .unwrap_or(0)
+    }
+
     /// Check if a version is included in a release
     fn version_exists(&self, version: &str) -> bool {
         self.releases.iter().any(|rel| rel.version == version)
@@ -436,12 +1039,22 @@ impl ChannelReleaseStore {
         Ok(())
     }
 
+    /// Set the flush epoch marker on the given releases
+    fn mark_flushed(&mut self, versions: &[VersionName], epoch: u32) {
+        for rel in &mut self.releases {
+            if rel.flushed.is_none() && versions.contains(&rel.version) {
+                rel.flushed = Some(epoch);
+            }
+        }
+    }
+
     /// Write the versions list contained in this store into the backing file.
     fn write_to_file(&self) -> anyhow::Result<()> {
         let encoded = serde_json::to_string_pretty(&self.releases)?;
         let mut f = OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(true)
             .open(&self.backing_file)?;
         f.write_all(encoded.as_bytes())?;
         Ok(())
@@ -450,45 +1063,242 @@ impl ChannelReleaseStore {
     /// Find entries not yet included in this release channel
     fn find_unreleased_entries(
         &self,
-        entries_dir: impl AsRef<Path>,
+        files: &[EntryFile],
+        package: Option<&str>,
     ) -> anyhow::Result<Vec<EntryName>> {
         let mut found = vec![];
 
-        for entry in entries_dir.as_ref().read_dir()? {
-            let entry = entry?;
-
-            let fname_os = entry.file_name();
-            let fname = fname_os.into_string().map_err(|_| {
-                anyhow::anyhow!("Failed to parse file name: {}", entry.path().display())
-            })?;
-
-            if !entry.metadata()?.is_file() || !fname.ends_with(".md") {
-                if fname != ".gitkeep" {
-                    eprintln!(
-                        "{}",
-                        format!(
-                            "Unexpected item in changelog entries dir: {}",
-                            entry.path().display()
-                        )
-                        .yellow()
-                    );
-                }
+        for file in files {
+            if self
+                .live_releases()
+                .flat_map(|rel| &rel.entries)
+                .any(|entryname| *entryname == file.name)
+            {
                 continue;
             }
 
-            let basename = fname.strip_suffix(".md").unwrap();
-
-            if !self
-                .releases
-                .iter()
-                .map(|rel| &rel.entries)
-                .flatten()
-                .any(|entryname| entryname == basename)
+            let entry = Entry::load(&file.path)?;
+            // Entries may be limited to some channels in their front-matter
+            if !entry.is_for_channel(&self.channel_name) {
+                continue;
+            }
+            if let Some(package) = package
+                && !entry.is_for_package(file.folder.as_deref(), package)
             {
-                found.push(basename.to_string());
+                continue;
             }
+            found.push(file.name.clone());
         }
 
         Ok(found)
     }
 }
+
+/// Entry file found in the entries dir
+struct EntryFile {
+    /// Entry name - file name without extension, prefixed with the package subfolder
+    name: EntryName,
+    /// Path of the file
+    path: PathBuf,
+    /// Package subfolder the file is in
+    folder: Option<String>,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::config::ChannelConfig;
+
+    #[test]
+    fn test_find_release_date() {
+        let config = Config::default();
+        let changelog =
+            "# Changelog\n\n## [1.1.0] - 2025-09-23\n- foo\n\n## [1.0.0] - 2025-09-15\n- bar\n";
+
+        assert_eq!(
+            find_release_date(&config, "1.0.0", changelog).unwrap(),
+            Some("2025-09-15".to_string())
+        );
+        assert_eq!(find_release_date(&config, "1.0", changelog).unwrap(), None);
+
+        let config = Config {
+            release_header: "Version {VERSION} ({DATE})".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_release_date(&config, "2.0", "## Version 2.0 (Jan 1, 2020)\n").unwrap(),
+            Some("Jan 1, 2020".to_string())
+        );
+    }
+
+    #[test]
+    fn test_regenerate_after_flush() {
+        let tmp = tempfile::tempdir().unwrap();
+        let ctx = AppContext::new(tmp.path(), Config::default());
+        let default = "default".to_string();
+
+        let mut store = Store::new(&ctx, true).unwrap();
+        for (entry, version) in [("login", "1.0.0"), ("logout", "1.1.0")] {
+            store
+                .create_entry(entry.to_string(), format!("# Fixes\n- {entry}\n"))
+                .unwrap();
+            let release = store.new_release(&default, version.to_string()).unwrap();
+            store.create_release(default.clone(), release).unwrap();
+        }
+        let rendered = store.render_changelog(&default, None).unwrap();
+
+        let plan = store.plan_flush();
+        assert_eq!(plan.entries, vec!["login", "logout"]);
+        store.flush(&plan, false).unwrap();
+
+        // The sections of flushed releases are kept from the changelog file
+        let store = Store::new(&ctx, false).unwrap();
+        assert_eq!(
+            store.render_changelog(&default, Some(&rendered)).unwrap(),
+            rendered
+        );
+        assert!(store.render_changelog(&default, None).is_err());
+    }
+
+    #[test]
+    fn test_find_release_section() {
+        let config = Config::default();
+        let changelog = "# Changelog\n\n## [1.1.0] - 2025-09-23\n\n### Fixes\n- foo\n\n## [1.0.0] - 2025-09-15\n- bar\n";
+
+        assert_eq!(
+            find_release_section(&config, "1.1.0", changelog).unwrap(),
+            Some("## [1.1.0] - 2025-09-23\n\n### Fixes\n- foo\n\n".to_string())
+        );
+        assert_eq!(
+            find_release_section(&config, "1.0.0", changelog).unwrap(),
+            Some("## [1.0.0] - 2025-09-15\n- bar\n\n".to_string())
+        );
+        assert_eq!(
+            find_release_section(&config, "1.0", changelog).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_migrate_on_write() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let manifest_path = dir.join("changelog/manifest.json");
+        std::fs::create_dir_all(dir.join("changelog/channels")).unwrap();
+        std::fs::write(&manifest_path, r#"{"format_version": 1}"#).unwrap();
+        std::fs::write(
+            dir.join("changelog/channels/default.json"),
+            r#"[{"version": "1.0.0", "entries": []}]"#,
+        )
+        .unwrap();
+
+        let ctx = AppContext::new(dir, Config::default());
+        let default = "default".to_string();
+
+        // Reading leaves the manifest alone
+        let store = Store::new(&ctx, false).unwrap();
+        assert!(store.version_exists("1.0.0"));
+        assert!(store.find_unreleased_changes(&default).unwrap().is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&manifest_path).unwrap(),
+            r#"{"format_version": 1}"#
+        );
+
+        store
+            .create_entry("login".to_string(), "- Login\n".to_string())
+            .unwrap();
+        let manifest: Manifest =
+            serde_json::from_str(&std::fs::read_to_string(&manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest.format_version, SUPPORTED_FORMAT_VERSION);
+    }
+
+    #[test]
+    fn test_check_entry_name() {
+        let tmp = tempfile::tempdir().unwrap();
+
+        let mut config = Config::default();
+        config
+            .packages
+            .insert("core".to_string(), Default::default());
+        let ctx = AppContext::new(tmp.path(), config);
+        let store = Store::new(&ctx, true).unwrap();
+
+        assert!(store.check_entry_name("login").is_ok());
+        assert!(store.check_entry_name("core/login").is_ok());
+        assert!(store.check_entry_name("feature/login").is_err());
+        assert!(store.check_entry_name("core/feature/login").is_err());
+        assert!(store.check_entry_name("feature\\login").is_err());
+        assert!(store.check_entry_name("..").is_err());
+        assert!(store.check_entry_name("core/..").is_err());
+        assert!(store.check_entry_name("").is_err());
+
+        assert!(
+            store
+                .create_entry("feature/login".to_string(), "- Login\n".to_string())
+                .is_err()
+        );
+        assert!(!store.store_path.join(DIR_ENTRIES).join("feature").exists());
+    }
+
+    #[test]
+    fn test_flush_channel_limited_entry() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+
+        let mut config = Config::default();
+        config.channels.insert(
+            "beta".to_string(),
+            ChannelConfig::Branch("/^beta\\//".to_string()),
+        );
+        let ctx = AppContext::new(dir, config);
+        let mut store = Store::new(&ctx, true).unwrap();
+        store
+            .create_entry("both".to_string(), "# Fixes\n- Both\n".to_string())
+            .unwrap();
+        store
+            .create_entry(
+                "beta-only".to_string(),
+                "+++\nchannels = [\"beta\"]\n+++\n# Fixes\n- Beta\n".to_string(),
+            )
+            .unwrap();
+
+        let beta = "beta".to_string();
+        let release = store
+            .new_release(&beta, "1.0.0-beta.1".to_string())
+            .unwrap();
+        assert_eq!(release.entries, vec!["beta-only", "both"]);
+        store.create_release(beta, release).unwrap();
+
+        // "both" is not released on the default channel yet, and keeps the whole beta release
+        assert!(store.plan_flush().entries.is_empty());
+
+        let default = "default".to_string();
+        let release = store.new_release(&default, "1.0.0".to_string()).unwrap();
+        assert_eq!(release.entries, vec!["both"]);
+        store.create_release(default.clone(), release).unwrap();
+
+        // The beta-only entry is never released on the default channel, that does not keep it
+        let plan = store.plan_flush();
+        assert_eq!(plan.entries, vec!["beta-only", "both"]);
+        assert_eq!(plan.releases.len(), 2);
+
+        store.flush(&plan, true).unwrap();
+        let archive_dir = store.make_archive_path(plan.epoch);
+        assert!(archive_dir.join("both.md").exists());
+        assert!(archive_dir.join("beta-only.md").exists());
+        assert!(!store.store_path.join(DIR_ENTRIES).join("both.md").exists());
+
+        // Reload from disk, the flushed releases still render from the archive
+        let store = Store::new(&ctx, false).unwrap();
+        assert!(store.find_unreleased_changes(&default).unwrap().is_empty());
+        assert!(store.plan_flush().entries.is_empty());
+        let release = &store.versions[&default].releases[0];
+        assert_eq!(release.flushed, Some(plan.epoch));
+        assert!(
+            store
+                .render_release(&default, release)
+                .unwrap()
+                .contains("- Both")
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Interactive prompts of the `cl` binary.
+//!
+//! Without the `interactive` feature nothing can be asked - the answers must come from command line options.
+
+use anyhow::bail;
+use std::ffi::OsStr;
+use std::fmt::Display;
+use std::io::IsTerminal;
+
+pub use prompt::*;
+
+/// Check if questions can be asked - prompts are compiled in and there is a terminal to ask in
+pub fn is_interactive() -> bool {
+    cfg!(feature = "interactive") && std::io::stdin().is_terminal()
+}
+
+/// Bail if we would need to prompt, but can't
+pub fn ensure_interactive(missing: &str) -> anyhow::Result<()> {
+    if !cfg!(feature = "interactive") {
+        bail!(
+            "clpack was built without the `interactive` feature, cannot ask for {missing}. Pass it as a command line option."
+        );
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!(
+            "Not running in a terminal, cannot ask for {missing}. Pass it as a command line option."
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "interactive")]
+mod prompt {
+    use super::*;
+
+    /// Ask a yes/no question
+    pub fn confirm(question: &str, default: bool) -> anyhow::Result<bool> {
+        Ok(inquire::Confirm::new(question)
+            .with_default(default)
+            .prompt()?)
+    }
+
+    /// Ask for one of the options, with the cursor at `starting` if given
+    pub fn select<T: Display>(
+        question: &str,
+        options: Vec<T>,
+        starting: Option<usize>,
+    ) -> anyhow::Result<T> {
+        let mut query = inquire::Select::new(question, options);
+        if let Some(index) = starting {
+            query = query.with_starting_cursor(index);
+        }
+        Ok(query.prompt()?)
+    }
+
+    /// Ask for any number of the options, with the `defaults` (indices) pre-selected
+    pub fn multi_select<T: Display>(
+        question: &str,
+        options: Vec<T>,
+        defaults: &[usize],
+    ) -> anyhow::Result<Vec<T>> {
+        Ok(inquire::MultiSelect::new(question, options)
+            .with_default(defaults)
+            .prompt()?)
+    }
+
+    /// Ask for a line of text, pre-filled with `initial`
+    pub fn text(question: &str, initial: &str, help: Option<&str>) -> anyhow::Result<String> {
+        let mut query = inquire::Text::new(question).with_initial_value(initial);
+        if let Some(help) = help {
+            query = query.with_help_message(help);
+        }
+        Ok(query.prompt()?)
+    }
+
+    /// Let the user edit a text in an editor. The command (with arguments) overrides $VISUAL / $EDITOR.
+    pub fn edit(
+        question: &str,
+        text: &str,
+        extension: &str,
+        command: &[&OsStr],
+    ) -> anyhow::Result<String> {
+        let mut editor = inquire::Editor::new(question)
+            .with_predefined_text(text)
+            .with_file_extension(extension);
+        if let Some((command, args)) = command.split_first() {
+            editor = editor.with_editor_command(command).with_args(args);
+        }
+        Ok(editor.prompt()?)
+    }
+}
+
+#[cfg(not(feature = "interactive"))]
+mod prompt {
+    use super::*;
+
+    /// Error for a question that can't be asked
+    fn compiled_out(question: &str) -> anyhow::Error {
+        anyhow::anyhow!(
+            "Cannot ask \"{question}\" - clpack was built without the `interactive` feature. Use the command line options."
+        )
+    }
+
+    /// Ask a yes/no question
+    pub fn confirm(question: &str, _default: bool) -> anyhow::Result<bool> {
+        Err(compiled_out(question))
+    }
+
+    /// Ask for one of the options, with the cursor at `starting` if given
+    pub fn select<T: Display>(
+        question: &str,
+        _options: Vec<T>,
+        _starting: Option<usize>,
+    ) -> anyhow::Result<T> {
+        Err(compiled_out(question))
+    }
+
+    /// Ask for any number of the options, with the `defaults` (indices) pre-selected
+    pub fn multi_select<T: Display>(
+        question: &str,
+        _options: Vec<T>,
+        _defaults: &[usize],
+    ) -> anyhow::Result<Vec<T>> {
+        Err(compiled_out(question))
+    }
+
+    /// Ask for a line of text, pre-filled with `initial`
+    pub fn text(question: &str, _initial: &str, _help: Option<&str>) -> anyhow::Result<String> {
+        Err(compiled_out(question))
+    }
+
+    /// Let the user edit a text in an editor. The command (with arguments) overrides $VISUAL / $EDITOR.
+    pub fn edit(
+        question: &str,
+        _text: &str,
+        _extension: &str,
+        _command: &[&OsStr],
+    ) -> anyhow::Result<String> {
+        Err(compiled_out(question))
+    }
+}
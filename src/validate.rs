@@ -0,0 +1,587 @@
+//! Semantic checks of the config, beyond what parsing catches
+
+use crate::config::{ChannelConfig, Config, VersionFormat, VersionSource};
+use crate::git::{BranchName, as_regex_pattern};
+use chrono::format::{Item, StrftimeItems};
+use indexmap::IndexMap;
+use std::borrow::Cow;
+
+/// Branch names tried against the channel patterns, to find channels that overlap
+const PROBE_BRANCHES: &[&str] = &[
+    "main", "master", "develop", "dev", "next", "stable", "test", "alpha", "beta", "rc", "eap",
+];
+
+/// Suffixes added to the probe branch names, for patterns like `^beta/.*`
+const PROBE_SUFFIXES: &[&str] = &["", "/1.0", "-1.0"];
+
+/// Problems found in the config
+#[derive(Debug, Default, PartialEq)]
+pub struct ValidationReport {
+    /// The config can't work as intended
+    pub errors: Vec<String>,
+    /// Probably a mistake
+    pub warnings: Vec<String>,
+}
+
+impl Config {
+    /// Check the config for problems that are only noticed when a setting is used - invalid patterns,
+    /// references to channels that don't exist, etc. All problems are collected, not only the first one.
+    pub fn validate(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        self.validate_channels(&mut report);
+        self.validate_packages(&mut report);
+        self.check_changelog_files(&mut report);
+
+        check_branch_pattern(
+            &mut report,
+            "branch_issue_pattern",
+            self.branch_issue_pattern.as_deref(),
+        );
+        check_branch_pattern(
+            &mut report,
+            "branch_version_pattern",
+            self.branch_version_pattern.as_deref(),
+        );
+        check_release_header(&mut report, "release_header", &self.release_header);
+
+        if StrftimeItems::new(&self.date_format).any(|item| item == Item::Error) {
+            report.errors.push(format!(
+                "date_format: invalid format \"{}\"",
+                self.date_format
+            ));
+        }
+
+        if self.sections.is_empty() {
+            report
+                .warnings
+                .push("sections: no sections to choose from when adding an entry".to_string());
+        }
+        for (i, section) in self.sections.iter().enumerate() {
+            if self.sections[..i].contains(section) {
+                report
+                    .warnings
+                    .push(format!("sections: \"{section}\" is listed twice"));
+            }
+        }
+
+        // Sections used by other settings - entries in them should be offered and ordered like the others
+        let referenced_sections = self
+            .conventional_commits
+            .types
+            .iter()
+            .map(|(kind, section)| (format!("conventional_commits.types.{kind}"), section))
+            .chain([(
+                "conventional_commits.breaking_section".to_string(),
+                &self.conventional_commits.breaking_section,
+            )])
+            .chain(
+                self.bump
+                    .major_sections
+                    .iter()
+                    .map(|section| ("bump.major_sections".to_string(), section)),
+            )
+            .chain(
+                self.bump
+                    .minor_sections
+                    .iter()
+                    .map(|section| ("bump.minor_sections".to_string(), section)),
+            );
+        for (key, section) in referenced_sections {
+            if !self.section_exists(section) {
+                report.warnings.push(format!(
+                    "{key}: section \"{section}\" is not among the sections"
+                ));
+            }
+        }
+
+        for (key, sources) in [
+            ("version_sources", &self.version_sources),
+            ("version_targets", &self.version_targets),
+        ] {
+            for (i, source) in sources.iter().enumerate() {
+                check_version_source(&mut report, &format!("{key}[{i}]"), source);
+            }
+        }
+
+        for channel in self.bump.prerelease.keys() {
+            self.check_channel_ref(&mut report, "bump.prerelease", channel);
+        }
+
+        if self.git.commit || self.git.tag {
+            for channel in &self.git.channels {
+                self.check_channel_ref(&mut report, "git.channels", channel);
+            }
+        }
+        if self.git.push && !self.git.tag {
+            report
+                .warnings
+                .push("git.push: only the tag is pushed, but git.tag is off".to_string());
+        }
+        if self.git.tag && !self.git.tag_name.contains("{VERSION}") {
+            report.errors.push(
+                "git.tag_name: must contain {VERSION}, tags of all releases would have the same name"
+                    .to_string(),
+            );
+        }
+
+        let youtrack = &self.integrations.youtrack;
+        if youtrack.enabled {
+            for channel in &youtrack.channels {
+                self.check_channel_ref(&mut report, "integrations.youtrack.channels", channel);
+            }
+            if youtrack.channels.is_empty() {
+                report.warnings.push(
+                    "integrations.youtrack: enabled, but channels is empty - YouTrack is never updated"
+                        .to_string(),
+                );
+            }
+            if youtrack
+                .released_state
+                .as_deref()
+                .unwrap_or_default()
+                .is_empty()
+                && youtrack
+                    .version_field
+                    .as_deref()
+                    .unwrap_or_default()
+                    .is_empty()
+            {
+                report.warnings.push(
+                    "integrations.youtrack: enabled, but neither released_state nor version_field is set - nothing to do"
+                        .to_string(),
+                );
+            }
+        }
+
+        let gitlab = &self.integrations.gitlab;
+        if gitlab.enabled {
+            for channel in &gitlab.channels {
+                self.check_channel_ref(&mut report, "integrations.gitlab.channels", channel);
+            }
+            if gitlab.create_release {
+                self.check_release_tag(&mut report, "integrations.gitlab", "GitLab");
+            }
+        }
+
+        let github = &self.integrations.github;
+        if github.enabled {
+            for channel in &github.channels {
+                self.check_channel_ref(&mut report, "integrations.github.channels", channel);
+            }
+            if github.create_release {
+                self.check_release_tag(&mut report, "integrations.github", "GitHub");
+            }
+        }
+
+        report
+    }
+
+    /// Check that the tag of a release created by an integration can be found on the server - it is pushed
+    /// by clpack, or created by the server at the packed commit
+    fn check_release_tag(&self, report: &mut ValidationReport, key: &str, server: &str) {
+        if !self.git.tag_name.contains("{VERSION}") {
+            report.errors.push(format!(
+                "{key}: create_release needs git.tag_name with {{VERSION}}, all releases would have the same tag"
+            ));
+        }
+        if self.git.tag && !self.git.push {
+            report.errors.push(format!(
+                "{key}: create_release with git.tag needs git.push, the tag would not be in {server}"
+            ));
+        }
+        if self.git.commit && !self.git.tag {
+            report.errors.push(format!(
+                "{key}: create_release with git.commit needs git.tag and git.push, the release commit would not be in {server}"
+            ));
+        }
+    }
+
+    /// Check if a section is configured, for all channels or for one of them
+    fn section_exists(&self, section: &str) -> bool {
+        self.sections.iter().any(|s| s == section)
+            || self.channels.values().any(|channel| {
+                channel
+                    .settings()
+                    .and_then(|settings| settings.sections.as_ref())
+                    .is_some_and(|sections| sections.iter().any(|s| s == section))
+            })
+    }
+
+    /// Check the channel patterns and the default channel
+    fn validate_channels(&self, report: &mut ValidationReport) {
+        if self.channels.is_empty() {
+            report
+                .errors
+                .push("channels: at least one channel must be defined".to_string());
+            return;
+        }
+
+        if !self.channels.contains_key(&self.default_channel) {
+            report.errors.push(format!(
+                "default_channel: \"{}\" is not among the channels",
+                self.default_channel
+            ));
+        }
+
+        let mut patterns_valid = true;
+        for (channel, channel_config) in &self.channels {
+            let template = channel_config.branch();
+            if let Some(pattern) = as_regex_pattern(template)
+                && let Err(e) = regex::Regex::new(pattern)
+            {
+                report
+                    .errors
+                    .push(format!("channels.{channel}: invalid regex {template}\n{e}"));
+                patterns_valid = false;
+            }
+
+            if let Some(settings) = channel_config.settings() {
+                check_branch_pattern(
+                    report,
+                    &format!("channels.{channel}.version_pattern"),
+                    settings.version_pattern.as_deref(),
+                );
+                if let Some(release_header) = &settings.release_header {
+                    check_release_header(
+                        report,
+                        &format!("channels.{channel}.release_header"),
+                        release_header,
+                    );
+                }
+            }
+        }
+
+        if !patterns_valid {
+            return;
+        }
+
+        // Overlapping patterns - the first matching channel wins, the other is never detected
+        let mut probes: Vec<String> = vec![];
+        let names = PROBE_BRANCHES
+            .iter()
+            .map(|s| s.to_string())
+            .chain(self.channels.keys().cloned())
+            .chain(
+                self.channels
+                    .values()
+                    .map(|ch| ch.branch().to_string())
+                    .filter(|t| as_regex_pattern(t).is_none()),
+            );
+        for name in names {
+            for suffix in PROBE_SUFFIXES {
+                let probe = format!("{name}{suffix}");
+                if !probes.contains(&probe) {
+                    probes.push(probe);
+                }
+            }
+        }
+
+        let mut reported = vec![];
+        for probe in probes {
+            let matching: Vec<&String> = self
+                .channels
+                .iter()
+                .filter(|(_, ch)| channel_matches(ch.branch(), &probe))
+                .map(|(channel, _)| channel)
+                .collect();
+            if matching.len() > 1 && !reported.contains(&matching) {
+                report.warnings.push(format!(
+                    "channels: branch \"{probe}\" matches channels {}, only \"{}\" (the first) is used",
+                    matching
+                        .iter()
+                        .map(|ch| format!("\"{ch}\""))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                    matching[0]
+                ));
+                reported.push(matching);
+            }
+        }
+    }
+
+    /// Check the packages of a monorepo
+    fn validate_packages(&self, report: &mut ValidationReport) {
+        for (name, package) in &self.packages {
+            if package.path.trim().is_empty() {
+                report
+                    .errors
+                    .push(format!("packages.{name}.path: must not be empty"));
+            }
+
+            if !package.channels.is_empty() {
+                let config = Config {
+                    channels: package.channels.clone(),
+                    ..self.clone()
+                };
+                let mut package_report = ValidationReport::default();
+                config.validate_channels(&mut package_report);
+                report.errors.extend(
+                    package_report
+                        .errors
+                        .into_iter()
+                        .map(|e| format!("packages.{name}.{e}")),
+                );
+                report.warnings.extend(
+                    package_report
+                        .warnings
+                        .into_iter()
+                        .map(|w| format!("packages.{name}.{w}")),
+                );
+            }
+        }
+
+        if !self.packages.is_empty() && self.git.tag && !self.git.tag_name.contains("{package}") {
+            report.warnings.push(
+                "git.tag_name: without {package}, packages releasing the same version would need the same tag"
+                    .to_string(),
+            );
+        }
+    }
+
+    /// Report channels (of any package) that would write to the same changelog file
+    fn check_changelog_files(&self, report: &mut ValidationReport) {
+        let mut configs = vec![];
+        if self.packages.is_empty() {
+            configs.push((String::new(), Cow::Borrowed(self)));
+        }
+        for name in self.packages.keys() {
+            if let Ok(config) = self.for_package(name) {
+                configs.push((format!("{name}/"), Cow::Owned(config)));
+            }
+        }
+
+        let mut files = IndexMap::<String, String>::new();
+        for (prefix, config) in &configs {
+            for channel in config.channels.keys() {
+                let file = config.changelog_file_name(channel);
+                let channel = format!("{prefix}{channel}");
+                if let Some(other) = files.get(&file) {
+                    report.errors.push(format!(
+                        "channels: \"{other}\" and \"{channel}\" write to the same changelog file {file}"
+                    ));
+                } else {
+                    files.insert(file, channel);
+                }
+            }
+        }
+    }
+
+    /// Report a reference to a channel that does not exist (in the project, or in any package)
+    fn check_channel_ref(&self, report: &mut ValidationReport, key: &str, channel: &str) {
+        if !self.channels.contains_key(channel)
+            && !self
+                .packages
+                .values()
+                .any(|package| package.channels.contains_key(channel))
+        {
+            report
+                .errors
+                .push(format!("{key}: channel \"{channel}\" does not exist"));
+        }
+    }
+}
+
+/// Check if a branch would be detected as a channel - same rules as `BranchName::parse_channel`
+fn channel_matches(template: &str, branch: &str) -> bool {
+    let config = Config {
+        channels: [("x".to_string(), ChannelConfig::Branch(template.to_string()))].into(),
+        ..Default::default()
+    };
+    BranchName(branch.to_string())
+        .parse_channel(&config)
+        .is_ok_and(|ch| ch.is_some())
+}
+
+/// Check an optional /regex/ with one capture group, used on branch names
+fn check_branch_pattern(report: &mut ValidationReport, key: &str, pattern: Option<&str>) {
+    let Some(pattern) = pattern.filter(|p| !p.is_empty()) else {
+        return;
+    };
+    match as_regex_pattern(pattern) {
+        Some(pattern) => check_capture_pattern(report, key, pattern),
+        None => report.errors.push(format!(
+            "{key}: must be a regex enclosed in slashes, found {pattern}"
+        )),
+    }
+}
+
+/// Check a release header pattern
+fn check_release_header(report: &mut ValidationReport, key: &str, header: &str) {
+    if !header.contains("{VERSION}") {
+        report
+            .errors
+            .push(format!("{key}: must contain {{VERSION}}"));
+    }
+}
+
+/// Check a regex that must have exactly one capture group
+fn check_capture_pattern(report: &mut ValidationReport, key: &str, pattern: &str) {
+    match regex::Regex::new(pattern) {
+        Ok(regex) if regex.captures_len() != 2 => {
+            report.errors.push(format!(
+                "{key}: there must be exactly one capture group, found {}",
+                regex.captures_len() - 1
+            ));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            report
+                .errors
+                .push(format!("{key}: invalid regex {pattern}\n{e}"));
+        }
+    }
+}
+
+/// Check a version source / target
+fn check_version_source(report: &mut ValidationReport, key: &str, source: &VersionSource) {
+    match (source.format, source.key.as_deref()) {
+        (VersionFormat::Regex, Some(pattern)) => check_capture_pattern(report, key, pattern),
+        (VersionFormat::Regex | VersionFormat::Toml | VersionFormat::Json, None) => {
+            report
+                .errors
+                .push(format!("{key}: format {:?} needs a key", source.format));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Config::default().validate(), ValidationReport::default());
+
+        let mut config: Config = toml::from_str(
+            r#"
+default_channel = "stable"
+branch_issue_pattern = '/^(\d+)-(.*)/'
+date_format = "%Q"
+
+[channels]
+default = "main"
+beta = '/^(main|beta)$/'
+broken = '/(/'
+
+[[version_targets]]
+file = "version.h"
+format = "regex"
+
+[integrations.youtrack]
+enabled = true
+channels = ["default", "eap"]
+version_field = "Available in version"
+"#,
+        )
+        .unwrap();
+
+        let report = config.validate();
+        assert_eq!(report.errors.len(), 6, "{report:#?}");
+        assert!(report.errors[0].starts_with("default_channel:"));
+        assert!(report.errors[1].starts_with("channels.broken:"));
+        assert!(report.errors[2].starts_with("branch_issue_pattern:"));
+        assert!(report.errors[3].starts_with("date_format:"));
+        assert!(report.errors[4].starts_with("version_targets[0]:"));
+        assert!(report.errors[5].contains("\"eap\""));
+        assert!(report.warnings.is_empty());
+
+        // Overlap is checked once the patterns are valid
+        config.channels.shift_remove("broken");
+        let report = config.validate();
+        assert_eq!(
+            report.warnings,
+            vec![
+                "channels: branch \"main\" matches channels \"default\", \"beta\", only \"default\" (the first) is used"
+            ]
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+changelog_file_channel = "CHANGELOG-next.md"
+
+[channels]
+default = "main"
+beta = "beta"
+eap = "eap"
+
+[channels.rc]
+branch = "rc"
+changelog_file = "CHANGELOG.md"
+version_pattern = "rc/.*"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().errors,
+            vec![
+                "channels.rc.version_pattern: must be a regex enclosed in slashes, found rc/.*",
+                "channels: \"beta\" and \"eap\" write to the same changelog file CHANGELOG-next.md",
+                "channels: \"default\" and \"rc\" write to the same changelog file CHANGELOG.md",
+            ]
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+[git]
+commit = true
+
+[integrations.gitlab]
+enabled = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().errors,
+            vec![
+                "integrations.gitlab: create_release with git.commit needs git.tag and git.push, the release commit would not be in GitLab"
+            ]
+        );
+        let mut config = config;
+        config.git.tag = true;
+        assert_eq!(
+            config.validate().errors,
+            vec![
+                "integrations.gitlab: create_release with git.tag needs git.push, the tag would not be in GitLab"
+            ]
+        );
+        config.git.push = true;
+        assert!(config.validate().errors.is_empty());
+
+        config.integrations.github.enabled = true;
+        config.git.push = false;
+        assert_eq!(
+            config.validate().errors,
+            vec![
+                "integrations.gitlab: create_release with git.tag needs git.push, the tag would not be in GitLab",
+                "integrations.github: create_release with git.tag needs git.push, the tag would not be in GitHub",
+            ]
+        );
+
+        let config: Config = toml::from_str(
+            r#"
+sections = ["Fixes", "New features"]
+
+[channels.beta]
+branch = "beta"
+sections = ["Fixes", "Breaking changes"]
+
+[conventional_commits.types]
+feat = "New features"
+perf = "Speedups"
+
+[bump]
+minor_sections = ["Features"]
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.validate().warnings,
+            vec![
+                "conventional_commits.types.perf: section \"Speedups\" is not among the sections",
+                "bump.minor_sections: section \"Features\" is not among the sections",
+            ]
+        );
+    }
+}
@@ -0,0 +1,636 @@
+//! Reading the current project version from manifest files
+
+use crate::AppContext;
+use crate::config::{BumpConfig, VersionFormat, VersionName, VersionSource};
+use crate::entry::Entry;
+use anyhow::{Context, bail};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::LazyLock;
+
+/// `v1.2.3-beta.4`, the prefix and pre-release part are optional
+static SEMVER: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        r"^(?P<prefix>[^\d]*)(?P<major>\d+)\.(?P<minor>\d+)\.(?P<patch>\d+)(?:-(?P<pre_id>[0-9A-Za-z-]+)\.(?P<pre_num>\d+))?$",
+    )
+    .unwrap()
+});
+
+/// Value of `--version` that takes the version from the configured version sources
+pub const VERSION_AUTO: &str = "auto";
+
+impl VersionFormat {
+    /// Default key paths for presets, empty for generic formats
+    fn preset_keys(&self) -> &'static [&'static str] {
+        match self {
+            VersionFormat::Cargo => &["package.version", "workspace.package.version"],
+            VersionFormat::Npm | VersionFormat::Composer => &["version"],
+            VersionFormat::Pyproject => &["project.version", "tool.poetry.version"],
+            VersionFormat::Toml | VersionFormat::Json | VersionFormat::Regex => &[],
+        }
+    }
+}
+
+impl VersionSource {
+    /// Key paths to try, in order - the configured key, or the defaults of the preset
+    fn keys(&self) -> Vec<&str> {
+        match &self.key {
+            Some(key) => vec![key.as_str()],
+            None => self.format.preset_keys().to_vec(),
+        }
+    }
+}
+
+/// Read the version from the first configured source that has one.
+///
+/// Sources whose file does not exist are skipped, other problems are errors.
+pub fn find_project_version(ctx: &AppContext) -> anyhow::Result<Option<(VersionName, String)>> {
+    for source in &ctx.config.version_sources {
+        let path = ctx.root.join(&source.file);
+        if !path.is_file() {
+            continue;
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading version source: {}", path.display()))?;
+        let version = read_version(source, &content)
+            .with_context(|| format!("Reading version from {}", source.file))?;
+        if let Some(version) = version {
+            return Ok(Some((version, source.file.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// Extract the version from the content of a version source file
+fn read_version(source: &VersionSource, content: &str) -> anyhow::Result<Option<VersionName>> {
+    let keys = source.keys();
+
+    let doc: serde_json::Value = match source.format {
+        VersionFormat::Cargo | VersionFormat::Pyproject | VersionFormat::Toml => {
+            let doc: toml::Table = toml::from_str(content).context("Invalid TOML")?;
+            serde_json::to_value(doc)?
+        }
+        VersionFormat::Npm | VersionFormat::Composer | VersionFormat::Json => {
+            serde_json::from_str(content).context("Invalid JSON")?
+        }
+        VersionFormat::Regex => {
+            let Some(pattern) = keys.first() else {
+                bail!("Version source format \"regex\" needs a key (the pattern)");
+            };
+            let pattern = regex::RegexBuilder::new(pattern)
+                .multi_line(true)
+                .build()
+                .context("Invalid version pattern")?;
+            return Ok(pattern
+                .captures(content)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.as_str().to_string()));
+        }
+    };
+
+    if keys.is_empty() {
+        bail!("Version source needs a key (dot-separated path to the version)");
+    }
+
+    for key in keys {
+        let value = key.split('.').try_fold(&doc, |value, part| value.get(part));
+        if let Some(version) = value.and_then(|v| v.as_str()) {
+            return Ok(Some(version.to_string()));
+        }
+    }
+    Ok(None)
+}
+
+/// Replace the version in the content of a version target file, keeping the rest of the file as it is.
+///
+/// The first key path (or the first match of the regex) that holds a version string is changed.
+pub fn replace_version(
+    source: &VersionSource,
+    content: &str,
+    version: &str,
+) -> anyhow::Result<String> {
+    let keys = source.keys();
+    if keys.is_empty() {
+        bail!("Version target needs a key");
+    }
+
+    let span = match source.format {
+        VersionFormat::Cargo | VersionFormat::Pyproject | VersionFormat::Toml => {
+            let doc = toml::de::DeTable::parse(content).context("Invalid TOML")?;
+            let doc = toml::de::DeValue::Table(doc.into_inner());
+            keys.iter().find_map(|key| {
+                let mut parts = key.split('.');
+                let mut value = doc.get(parts.next()?)?;
+                for part in parts {
+                    value = value.get_ref().get(part)?;
+                }
+                value.get_ref().is_str().then(|| value.span())
+            })
+        }
+        VersionFormat::Npm | VersionFormat::Composer | VersionFormat::Json => {
+            serde_json::from_str::<serde_json::Value>(content).context("Invalid JSON")?;
+            keys.iter().find_map(|key| {
+                JsonScanner::new(content).find_string(&key.split('.').collect::<Vec<_>>())
+            })
+        }
+        VersionFormat::Regex => {
+            let pattern = regex::RegexBuilder::new(keys[0])
+                .multi_line(true)
+                .build()
+                .context("Invalid version pattern")?;
+            pattern
+                .captures(content)
+                .and_then(|caps| caps.get(1))
+                .map(|m| m.range())
+        }
+    };
+
+    let Some(span) = span else {
+        bail!("Version not found");
+    };
+
+    let replacement = match source.format {
+        VersionFormat::Regex => version.to_string(),
+        // Literal strings stay literal
+        _ if content[span.clone()].starts_with('\'') => format!("'{version}'"),
+        _ => format!("\"{}\"", version.replace('\\', "\\\\").replace('"', "\\\"")),
+    };
+
+    let mut new_content = content.to_string();
+    new_content.replace_range(span, &replacement);
+    Ok(new_content)
+}
+
+/// Planned change of a version target file
+#[derive(Debug)]
+pub struct VersionUpdate {
+    /// File name, as configured
+    pub file: String,
+    /// Full path
+    pub path: PathBuf,
+    /// Current content
+    pub old_content: String,
+    /// Content with the new version
+    pub new_content: String,
+}
+
+impl VersionUpdate {
+    /// Write the new content
+    pub fn apply(&self) -> anyhow::Result<()> {
+        std::fs::write(&self.path, &self.new_content)
+            .with_context(|| format!("Writing version target: {}", self.path.display()))
+    }
+}
+
+/// Prepare the changes of all version targets, without writing anything yet.
+/// Fails if any target is missing or does not contain a version.
+pub fn plan_version_updates(ctx: &AppContext, version: &str) -> anyhow::Result<Vec<VersionUpdate>> {
+    ctx.config
+        .version_targets
+        .iter()
+        .map(|target| {
+            let path = ctx.root.join(&target.file);
+            let old_content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Reading version target: {}", path.display()))?;
+            let new_content = replace_version(target, &old_content, version)
+                .with_context(|| format!("Writing version into {}", target.file))?;
+            Ok(VersionUpdate {
+                file: target.file.clone(),
+                path,
+                old_content,
+                new_content,
+            })
+        })
+        .collect()
+}
+
+/// Minimal JSON scanner, to find the exact location of a value without reformatting the file.
+/// The text must be valid JSON (checked by the caller).
+struct JsonScanner<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> JsonScanner<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            text: text.as_bytes(),
+            pos: 0,
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.text.len() && self.text[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.text.get(self.pos).copied()
+    }
+
+    /// Skip a string, return its range including the quotes
+    fn skip_string(&mut self) -> Range<usize> {
+        let start = self.pos;
+        self.pos += 1;
+        while self.pos < self.text.len() {
+            match self.text[self.pos] {
+                b'\\' => self.pos += 2,
+                b'"' => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => self.pos += 1,
+            }
+        }
+        start..self.pos
+    }
+
+    /// Skip any value
+    fn skip_value(&mut self) {
+        match self.peek() {
+            Some(b'"') => {
+                self.skip_string();
+            }
+            Some(b'{') | Some(b'[') => {
+                let mut depth = 0;
+                while let Some(c) = self.peek() {
+                    match c {
+                        b'"' => {
+                            self.skip_string();
+                            continue;
+                        }
+                        b'{' | b'[' => depth += 1,
+                        b'}' | b']' => depth -= 1,
+                        _ => {}
+                    }
+                    self.pos += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => {
+                // Number, bool or null
+                while self.pos < self.text.len()
+                    && !matches!(self.text[self.pos], b',' | b'}' | b']')
+                    && !self.text[self.pos].is_ascii_whitespace()
+                {
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    /// Find the range of a string value (including the quotes) by its key path
+    fn find_string(&mut self, path: &[&str]) -> Option<Range<usize>> {
+        let (first, rest) = path.split_first()?;
+        if self.peek()? != b'{' {
+            return None;
+        }
+        self.pos += 1;
+        loop {
+            match self.peek()? {
+                b'}' => return None,
+                b',' => {
+                    self.pos += 1;
+                    continue;
+                }
+                b'"' => {}
+                _ => return None,
+            }
+            let key_span = self.skip_string();
+            let key: String = serde_json::from_slice(&self.text[key_span]).ok()?;
+            if self.peek()? != b':' {
+                return None;
+            }
+            self.pos += 1;
+
+            if key == *first {
+                if rest.is_empty() {
+                    return (self.peek()? == b'"').then(|| self.skip_string());
+                }
+                return self.find_string(rest);
+            }
+            self.skip_value();
+        }
+    }
+}
+
+/// Semver bump level, ordered from the smallest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BumpLevel {
+    /// 1.2.3 -> 1.2.4
+    Patch,
+    /// 1.2.3 -> 1.3.0
+    Minor,
+    /// 1.2.3 -> 2.0.0
+    Major,
+}
+
+impl Display for BumpLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BumpLevel::Patch => "patch",
+            BumpLevel::Minor => "minor",
+            BumpLevel::Major => "major",
+        })
+    }
+}
+
+/// Find the bump level needed for a set of entries. None if there are no entries.
+pub fn bump_level<'a>(
+    config: &BumpConfig,
+    entries: impl IntoIterator<Item = &'a Entry>,
+) -> Option<BumpLevel> {
+    let matches =
+        |sections: &[String], name: &str| sections.iter().any(|s| s.eq_ignore_ascii_case(name));
+
+    entries
+        .into_iter()
+        .map(|entry| {
+            if entry.meta.breaking
+                || entry
+                    .sections
+                    .keys()
+                    .any(|name| matches(&config.major_sections, name))
+            {
+                BumpLevel::Major
+            } else if entry
+                .sections
+                .keys()
+                .any(|name| matches(&config.minor_sections, name))
+            {
+                BumpLevel::Minor
+            } else {
+                BumpLevel::Patch
+            }
+        })
+        .max()
+}
+
+/// Version parsed as semver, keeping a prefix like `v`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemVer {
+    prefix: String,
+    major: u64,
+    minor: u64,
+    patch: u64,
+    /// Pre-release identifier and number, e.g. `beta.2`
+    pre: Option<(String, u64)>,
+}
+
+impl SemVer {
+    /// Parse a version. None if it is not in the supported semver form.
+    pub fn parse(version: &str) -> Option<Self> {
+        let caps = SEMVER.captures(version)?;
+        Some(Self {
+            prefix: caps["prefix"].to_string(),
+            major: caps["major"].parse().ok()?,
+            minor: caps["minor"].parse().ok()?,
+            patch: caps["patch"].parse().ok()?,
+            pre: match (caps.name("pre_id"), caps.name("pre_num")) {
+                (Some(id), Some(num)) => {
+                    Some((id.as_str().to_string(), num.as_str().parse().ok()?))
+                }
+                _ => None,
+            },
+        })
+    }
+
+    /// Check if a pre-release of this version already contains changes of the given level,
+    /// i.e. releasing it (or its next pre-release) is enough.
+    fn pre_covers(&self, level: BumpLevel) -> bool {
+        match level {
+            BumpLevel::Major => self.minor == 0 && self.patch == 0,
+            BumpLevel::Minor => self.patch == 0,
+            BumpLevel::Patch => true,
+        }
+    }
+
+    /// The version without its pre-release part, bumped
+    fn bump_core(&self, level: BumpLevel) -> Self {
+        let (major, minor, patch) = match level {
+            BumpLevel::Major => (self.major + 1, 0, 0),
+            BumpLevel::Minor => (self.major, self.minor + 1, 0),
+            BumpLevel::Patch => (self.major, self.minor, self.patch + 1),
+        };
+        Self {
+            prefix: self.prefix.clone(),
+            major,
+            minor,
+            patch,
+            pre: None,
+        }
+    }
+
+    /// Next version for changes of the given level.
+    ///
+    /// With a pre-release identifier, the result is a pre-release (`1.2.0-beta.1`, then `1.2.0-beta.2`).
+    /// Without it, a pre-release is promoted to its stable version if that covers the changes.
+    pub fn bump(&self, level: BumpLevel, pre_id: Option<&str>) -> Self {
+        let mut next = match &self.pre {
+            Some(_) if self.pre_covers(level) => Self {
+                pre: None,
+                ..self.clone()
+            },
+            _ => self.bump_core(level),
+        };
+
+        if let Some(pre_id) = pre_id {
+            let num = match &self.pre {
+                Some((id, num)) if id == pre_id && next.core_eq(self) => num + 1,
+                _ => 1,
+            };
+            next.pre = Some((pre_id.to_string(), num));
+        }
+        next
+    }
+
+    /// Compare without the pre-release part
+    fn core_eq(&self, other: &Self) -> bool {
+        (self.major, self.minor, self.patch) == (other.major, other.minor, other.patch)
+    }
+}
+
+impl Display for SemVer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}{}.{}.{}",
+            self.prefix, self.major, self.minor, self.patch
+        )?;
+        if let Some((id, num)) = &self.pre {
+            write!(f, "-{id}.{num}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(format: VersionFormat, key: Option<&str>) -> VersionSource {
+        VersionSource {
+            file: String::new(),
+            format,
+            key: key.map(|k| k.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_read_version() {
+        let cargo = "[package]\nname = \"foo\"\nversion = \"1.2.3\"\n";
+        assert_eq!(
+            read_version(&source(VersionFormat::Cargo, None), cargo).unwrap(),
+            Some("1.2.3".to_string())
+        );
+
+        // Inherited from the workspace - not a string
+        let cargo =
+            "[package]\nversion.workspace = true\n[workspace.package]\nversion = \"2.0.0\"\n";
+        assert_eq!(
+            read_version(&source(VersionFormat::Cargo, None), cargo).unwrap(),
+            Some("2.0.0".to_string())
+        );
+
+        let npm = r#"{"name": "foo", "version": "0.1.0-beta.1"}"#;
+        assert_eq!(
+            read_version(&source(VersionFormat::Npm, None), npm).unwrap(),
+            Some("0.1.0-beta.1".to_string())
+        );
+
+        let poetry = "[tool.poetry]\nversion = \"3.1\"\n";
+        assert_eq!(
+            read_version(&source(VersionFormat::Pyproject, None), poetry).unwrap(),
+            Some("3.1".to_string())
+        );
+
+        let json = r#"{"app": {"version": "4.0"}}"#;
+        assert_eq!(
+            read_version(&source(VersionFormat::Json, Some("app.version")), json).unwrap(),
+            Some("4.0".to_string())
+        );
+        assert!(read_version(&source(VersionFormat::Json, None), json).is_err());
+
+        let header = "#pragma once\n#define APP_VERSION \"5.6.7\"\n";
+        assert_eq!(
+            read_version(
+                &source(
+                    VersionFormat::Regex,
+                    Some(r#"^#define APP_VERSION "([^"]+)""#)
+                ),
+                header
+            )
+            .unwrap(),
+            Some("5.6.7".to_string())
+        );
+
+        assert_eq!(
+            read_version(&source(VersionFormat::Npm, None), "{}").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_replace_version() {
+        let cargo = "[package]\nname = \"foo\"  # name\nversion = \"1.2.3\" # version\n\n[dependencies]\nbar = { version = \"1.0\" }\n";
+        assert_eq!(
+            replace_version(&source(VersionFormat::Cargo, None), cargo, "1.3.0").unwrap(),
+            "[package]\nname = \"foo\"  # name\nversion = \"1.3.0\" # version\n\n[dependencies]\nbar = { version = \"1.0\" }\n"
+        );
+
+        let literal = "[tool.poetry]\nversion = '0.1'\n";
+        assert_eq!(
+            replace_version(&source(VersionFormat::Pyproject, None), literal, "0.2").unwrap(),
+            "[tool.poetry]\nversion = '0.2'\n"
+        );
+
+        let npm = "{\n  \"name\": \"x\",\n  \"deps\": {\"version\": \"9\", \"a\": [1, {\"b\": \"}\"}]},\n  \"version\" :  \"1.0.0\"\n}\n";
+        assert_eq!(
+            replace_version(&source(VersionFormat::Npm, None), npm, "1.1.0").unwrap(),
+            "{\n  \"name\": \"x\",\n  \"deps\": {\"version\": \"9\", \"a\": [1, {\"b\": \"}\"}]},\n  \"version\" :  \"1.1.0\"\n}\n"
+        );
+
+        let json = r#"{"app": {"version": "1", "n": null}}"#;
+        assert_eq!(
+            replace_version(&source(VersionFormat::Json, Some("app.version")), json, "2").unwrap(),
+            r#"{"app": {"version": "2", "n": null}}"#
+        );
+
+        let header = "#define APP_VERSION \"5.6.7\"\n";
+        assert_eq!(
+            replace_version(
+                &source(VersionFormat::Regex, Some(r#"APP_VERSION "([^"]+)""#)),
+                header,
+                "5.7.0"
+            )
+            .unwrap(),
+            "#define APP_VERSION \"5.7.0\"\n"
+        );
+
+        assert!(replace_version(&source(VersionFormat::Npm, None), "{}", "1").is_err());
+    }
+
+    #[test]
+    fn test_bump_level() {
+        let config = BumpConfig::default();
+        let fix = Entry::parse("# Fixes\n- fix\n").unwrap();
+        let internal = Entry::parse("# Internal\n- refactor\n").unwrap();
+        let feature = Entry::parse("# New features\n- feature\n").unwrap();
+        let breaking = Entry::parse("+++\nbreaking = true\n+++\n# Fixes\n- fix\n").unwrap();
+
+        assert_eq!(bump_level(&config, []), None);
+        assert_eq!(bump_level(&config, [&internal]), Some(BumpLevel::Patch));
+        assert_eq!(
+            bump_level(&config, [&fix, &feature]),
+            Some(BumpLevel::Minor)
+        );
+        assert_eq!(
+            bump_level(&config, [&fix, &breaking, &feature]),
+            Some(BumpLevel::Major)
+        );
+    }
+
+    #[test]
+    fn test_bump() {
+        let bump = |version: &str, level, pre_id| {
+            SemVer::parse(version)
+                .unwrap()
+                .bump(level, pre_id)
+                .to_string()
+        };
+
+        assert_eq!(bump("1.2.3", BumpLevel::Patch, None), "1.2.4");
+        assert_eq!(bump("v1.2.3", BumpLevel::Minor, None), "v1.3.0");
+        assert_eq!(bump("1.2.3", BumpLevel::Major, None), "2.0.0");
+
+        // Pre-releases
+        assert_eq!(
+            bump("1.2.3", BumpLevel::Minor, Some("beta")),
+            "1.3.0-beta.1"
+        );
+        assert_eq!(
+            bump("1.3.0-beta.1", BumpLevel::Patch, Some("beta")),
+            "1.3.0-beta.2"
+        );
+        assert_eq!(
+            bump("1.3.0-beta.2", BumpLevel::Major, Some("beta")),
+            "2.0.0-beta.1"
+        );
+        assert_eq!(
+            bump("1.3.0-alpha.4", BumpLevel::Minor, Some("beta")),
+            "1.3.0-beta.1"
+        );
+
+        // Promoting a pre-release
+        assert_eq!(bump("1.3.0-beta.2", BumpLevel::Minor, None), "1.3.0");
+        assert_eq!(bump("1.3.1-beta.2", BumpLevel::Minor, None), "1.4.0");
+
+        assert_eq!(SemVer::parse("3.40"), None);
+        assert_eq!(SemVer::parse("1.2.3-rc1"), None);
+    }
+}